@@ -0,0 +1,137 @@
+use std::sync::PoisonError;
+use std::thread;
+use crate::message::message::Message;
+use crate::server::action::{Action, ActionInfo, AtomicActions, GoalStatus, GoalUpdate};
+use crate::server::handshake::Session;
+use crate::server::info::{publish_event, SystemEvent};
+use crate::server::protocol::{clone_connection, Frame, FrameKind};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::validate_message;
+use crate::server::transport::Connection;
+
+/// Server side action advertisement
+pub fn handle_message_kind_action(mut stream: Connection, message: Message, session: &Session, actions: AtomicActions, topics: AtomicTopics) {
+    let action_name = message.action.unwrap();
+
+    let info = ActionInfo {
+        name: action_name.clone(),
+        goal_type: message.goal_type,
+        feedback_type: message.feedback_type,
+        result_type: message.result_type,
+    };
+
+    let Some(provider_stream) = clone_connection(&mut stream) else {
+        return;
+    };
+
+    let action = {
+        let mut actions_list = actions.lock();
+
+        if actions_list.iter().any(|action| action.info.name == action_name) {
+            Err(Frame::error("action_exists", format!("Action \"{}\" is already advertised", action_name)))
+        }
+        else {
+            match Action::new(info, provider_stream) {
+                Ok(action) => {
+                    let provider = action.provider.clone();
+                    actions_list.push(action);
+                    Ok(provider)
+                }
+                Err(error) => Err(Frame::error("unknown_message_type", error)),
+            }
+        }
+    };
+
+    match action {
+        Ok(provider) => {
+            // Goals are written to the same connection, the provider must get its acknowledgement first
+            Frame::ack().write_to(&mut *provider.lock().unwrap_or_else(PoisonError::into_inner)).ok();
+            println!("Advertised action {}", action_name);
+
+            // The provider connection lives as long as the action, it does not hold a worker
+            let client = session.client();
+            thread::spawn(move || read_provider_updates(stream, client, action_name, actions, topics));
+        }
+        Err(error) => {
+            error.write_to(&mut stream).ok();
+        }
+    }
+}
+
+/// Forwards the feedbacks and results of a provider to the goal clients, until its connection is closed
+fn read_provider_updates(mut stream: Connection, client: String, action_name: String, actions: AtomicActions, topics: AtomicTopics) {
+    while let Ok(Some(frame)) = Frame::read_from(&mut stream) {
+        if frame.kind != FrameKind::Request {
+            continue;
+        }
+
+        let update: Message = match frame.json() {
+            Ok(update) => update,
+            Err(error) => {
+                println!("Malformed update from action {}: {}", action_name, error);
+                continue;
+            }
+        };
+
+        let Some(goal) = update.goal else {
+            println!("Action {} sent an update without goal", action_name);
+            continue;
+        };
+
+        let status = match update.kind.as_str() {
+            "feedback" => GoalStatus::Active,
+            "result" => GoalStatus::Succeeded,
+            "canceled" => GoalStatus::Canceled,
+            "aborted" => GoalStatus::Aborted,
+            kind => {
+                println!("Action {} sent an update of unknown kind {}", action_name, kind);
+                continue;
+            }
+        };
+
+        let (feedback_schema, result_schema) = actions.lock()
+            .iter()
+            .find(|action| action.info.name == action_name)
+            .map(|action| (action.feedback_schema.clone(), action.result_schema.clone()))
+            .unwrap_or_default();
+
+        let handled = match status {
+            GoalStatus::Active => {
+                match feedback_schema.map(|schema| validate_message(&schema, update.message.as_ref())) {
+                    Some(Err(errors)) => {
+                        println!("Action {} sent a badly formatted feedback for goal {}: {}", action_name, goal, errors.join(", "));
+                        true
+                    }
+                    _ => actions.send_feedback(&action_name, goal, update.message),
+                }
+            }
+            GoalStatus::Succeeded => {
+                let frame = match result_schema.map(|schema| validate_message(&schema, update.message.as_ref())) {
+                    Some(Err(errors)) => {
+                        let error = format!("Action \"{}\" sent a badly formatted result", action_name);
+                        Frame::error_with_details("invalid_result", error, errors)
+                    }
+                    _ => GoalUpdate { goal, status, message: update.message }.to_frame(),
+                };
+
+                actions.finish_goal(&action_name, goal, frame)
+            }
+            _ => actions.finish_goal(&action_name, goal, GoalUpdate { goal, status, message: update.message }.to_frame()),
+        };
+
+        if !handled {
+            println!("Action {} sent an update for unknown goal {}", action_name, goal);
+        }
+        else if status != GoalStatus::Active {
+            println!("Goal {} of action {} is over: {:?}", goal, action_name, status);
+        }
+    }
+
+    actions.remove_action(&action_name);
+    println!("Action {} is no longer available", action_name);
+
+    publish_event(&topics, SystemEvent::ClientDisconnected {
+        client,
+        reason: format!("provider of action {} is gone", action_name),
+    });
+}
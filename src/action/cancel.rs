@@ -0,0 +1,44 @@
+use std::net::Shutdown;
+use std::process::exit;
+use crate::message::message::Message;
+use crate::server::action::AtomicActions;
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::transport::Connection;
+
+/// Server side goal cancellation, the provider decides when the goal is over
+pub fn handle_message_kind_cancel(mut stream: Connection, message: Message, actions: AtomicActions) {
+    let action_name = message.action.clone().unwrap();
+    let goal = message.goal.unwrap_or_default();
+
+    if !actions.has_goal(&action_name, goal) || !actions.forward_cancel(&action_name, goal) {
+        let error = format!("Goal {} of action \"{}\" not found", goal, action_name);
+        Frame::error("unknown_goal", error).write_to(&mut stream).ok();
+        return;
+    }
+
+    println!("Forwarded cancellation of goal {} to action {}", goal, action_name);
+
+    Frame::ack().write_to(&mut stream).ok();
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side goal cancellation
+pub fn handle_action_cancel_command(action_name: String, goal: u64, server: &str) {
+    let data = Message {
+        kind: String::from("cancel"),
+        action: Some(action_name),
+        goal: Some(goal),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Cancellation rejected: {}", error.message);
+        exit(1);
+    }
+
+    println!("Cancellation of goal {} sent", goal);
+}
@@ -0,0 +1,163 @@
+use std::process::exit;
+use std::sync::{Arc, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::message::message::Message;
+use crate::server::action::{ActionGoal, AtomicActions, GoalAccepted, GoalStatus, GoalUpdate};
+use crate::server::protocol::{clone_connection, connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::topic::validate_message;
+use crate::server::transport::Connection;
+
+/// Server side goal submission
+pub fn handle_message_kind_goal(mut stream: Connection, message: Message, actions: AtomicActions) {
+    let action_name = message.action.clone().unwrap();
+
+    let action = actions.lock()
+        .iter()
+        .find(|action| action.info.name == action_name)
+        .map(|action| (action.goal_schema.clone(), Arc::clone(&action.provider)));
+
+    let Some((schema, provider)) = action else {
+        let error = format!("Action \"{}\" not found", action_name);
+        Frame::error("unknown_action", error).write_to(&mut stream).ok();
+        return;
+    };
+
+    if let Some(schema) = schema {
+        if let Err(errors) = validate_message(&schema, message.message.as_ref()) {
+            let error = format!("Wrong goal format for action \"{}\"", action_name);
+            Frame::error_with_details("invalid_goal", error, errors).write_to(&mut stream).ok();
+            return;
+        }
+    }
+
+    // The client connection receives the acknowledgement, the feedbacks and the result from the goal writer
+    let Some(caller) = clone_connection(&mut stream) else {
+        return;
+    };
+
+    let Some(goal) = actions.add_goal(&action_name, caller) else {
+        let error = format!("Action \"{}\" not found", action_name);
+        Frame::error("unknown_action", error).write_to(&mut stream).ok();
+        return;
+    };
+
+    let action_goal = ActionGoal {
+        goal,
+        cancel: false,
+        message: message.message,
+    };
+
+    let forwarded = Frame::data(serde_json::to_vec(&action_goal).unwrap())
+        .write_to(&mut *provider.lock().unwrap_or_else(PoisonError::into_inner))
+        .is_ok();
+
+    if forwarded {
+        println!("Forwarded goal {} to action {}", goal, action_name);
+    }
+    else {
+        let update = GoalUpdate {
+            goal,
+            status: GoalStatus::Aborted,
+            message: None,
+        };
+
+        actions.finish_goal(&action_name, goal, update.to_frame());
+    }
+}
+
+/// Client side goal submission, waits for the result of the goal
+pub fn handle_action_send_goal_command(action_name: String, goal: Option<String>, with_feedback: bool, server: &str) {
+    let content = goal.map(|goal| {
+        serde_json::from_str(&goal).unwrap_or_else(|error| {
+            println!("Could not parse goal to JSON: {}", error);
+            exit(1);
+        })
+    });
+
+    let data = Message {
+        kind: String::from("goal"),
+        action: Some(action_name.clone()),
+        message: content,
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Goal rejected: {}", error.message);
+
+        for detail in error.details {
+            println!("  - {}", detail);
+        }
+
+        exit(1);
+    }
+
+    let accepted: GoalAccepted = response.json().expect("Malformed goal response");
+    println!("Goal {} accepted", accepted.goal);
+
+    let cancel = Message {
+        kind: String::from("cancel"),
+        action: Some(action_name),
+        goal: Some(accepted.goal),
+        ..Default::default()
+    };
+    let server = server.to_string();
+    let canceling = AtomicBool::new(false);
+
+    // The first interruption asks the provider to cancel the goal, the next one leaves without waiting
+    ctrlc::set_handler(move || {
+        if canceling.swap(true, Ordering::Relaxed) {
+            exit(1);
+        }
+
+        println!("Canceling goal {}...", cancel.goal.unwrap());
+        let mut cancel_stream = connect(&server);
+        send_request(&mut cancel_stream, &cancel).ok();
+    }).expect("Could not set the interruption handler");
+
+    while let Some(frame) = Frame::read_from(&mut stream).expect("Connection to the server lost") {
+        if frame.kind == FrameKind::Error {
+            let error: ErrorReply = frame.json().expect("Malformed error response");
+            println!("Goal failed: {}", error.message);
+
+            for detail in error.details {
+                println!("  - {}", detail);
+            }
+
+            exit(1);
+        }
+
+        if frame.kind != FrameKind::Data {
+            continue;
+        }
+
+        let update: GoalUpdate = frame.json().expect("Malformed goal update");
+        let message = update.message.map(|message| message.to_string()).unwrap_or_default();
+
+        match update.status {
+            GoalStatus::Active => {
+                if with_feedback {
+                    println!("Feedback: {}", message);
+                }
+            }
+            GoalStatus::Succeeded => {
+                println!("{}", message);
+                exit(0);
+            }
+            GoalStatus::Canceled => {
+                println!("Goal {} canceled {}", update.goal, message);
+                exit(1);
+            }
+            GoalStatus::Aborted => {
+                println!("Goal {} aborted {}", update.goal, message);
+                exit(1);
+            }
+        }
+    }
+
+    println!("Connection closed before the goal result");
+    exit(1);
+}
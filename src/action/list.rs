@@ -0,0 +1,67 @@
+use std::net::Shutdown;
+use std::process::exit;
+use crate::message::message::Message;
+use crate::server::action::{ActionInfo, AtomicActions};
+use crate::server::protocol::{connect, Frame, FrameKind, send_request};
+use crate::server::transport::Connection;
+
+/// Server side action list
+pub fn handle_message_kind_action_list(mut stream: Connection, actions: AtomicActions) {
+    let actions_info: Vec<ActionInfo> = actions.lock()
+        .iter()
+        .map(|action| action.info.clone())
+        .collect();
+
+    Frame::ack_with(&actions_info).write_to(&mut stream).ok();
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Retrieves the actions advertised on the server
+pub fn get_actions(server: &str) -> Vec<ActionInfo> {
+    let data = Message {
+        kind: String::from("action_list"),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind != FrameKind::Ack {
+        println!("Could not retrieve the actions list");
+        exit(1);
+    }
+
+    response.json().expect("Malformed actions list")
+}
+
+/// Client side action list
+pub fn handle_action_list_command(with_message_types: bool, server: &str) {
+    let actions = get_actions(server);
+
+    let mut separator = "--------------------".to_string();
+
+    print!("{0: <20}", "Action name");
+
+    if with_message_types {
+        print!("{0: <20}{1: <20}{2: <20}", "Goal type", "Feedback type", "Result type");
+        separator += "------------------------------------------------------------";
+    }
+
+    println!();
+    println!("{separator}");
+
+    for action in actions {
+        print!("{0: <20}", action.name);
+
+        if with_message_types {
+            print!(
+                "{0: <20}{1: <20}{2: <20}",
+                action.goal_type.unwrap_or("None".to_string()),
+                action.feedback_type.unwrap_or("None".to_string()),
+                action.result_type.unwrap_or("None".to_string())
+            );
+        }
+
+        println!();
+    }
+}
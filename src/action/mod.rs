@@ -0,0 +1,4 @@
+pub mod advertise;
+pub mod goal;
+pub mod cancel;
+pub mod list;
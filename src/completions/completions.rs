@@ -3,10 +3,10 @@ use clap::Command;
 use clap_complete::generate_to;
 use clap_complete::Shell::{Bash, Elvish, Fish, PowerShell, Zsh};
 use crate::{get_temp_folder, string_to_static_str};
-use crate::message::message::{get_default, get_topics};
+use crate::message::message::{get_cached_topics, get_default};
 use crate::node::node::get_nodes;
 
-pub fn generate_completions(mut cmd: Command, cmd_name: String, no_sourcing: bool) {
+pub fn generate_completions(mut cmd: Command, cmd_name: String, no_sourcing: bool, server: &str) {
     for command in cmd.get_subcommands_mut() {
 
         match command.get_name() {
@@ -14,10 +14,12 @@ pub fn generate_completions(mut cmd: Command, cmd_name: String, no_sourcing: boo
                 for topic_command in command.get_subcommands_mut() {
                     match topic_command.get_name() {
                         "sub" | "pub" => {
-                            let topics = get_topics();
+                            // Completions are generated quietly from the topics file, without reaching the server
+                            let topics = get_cached_topics(server).unwrap_or_default();
 
-                            for (topic, message_type) in topics {
-                                let topic = string_to_static_str(topic);
+                            for topic_info in topics {
+                                let message_type = topic_info.message_type;
+                                let topic = string_to_static_str(topic_info.name);
 
                                 if topic.is_empty() {
                                     continue;
@@ -21,6 +21,9 @@ use crate::node::list::list_nodes;
 use crate::node::run::run_node;
 
 mod topic;
+mod service;
+mod action;
+mod param;
 mod message;
 mod package;
 mod server;
@@ -28,15 +31,40 @@ mod build;
 mod completions;
 mod node;
 
-use crate::server::serve::run_server;
+use crate::server::compression::Compression;
+use crate::server::encoding::Encoding;
+use crate::server::qos::{DEFAULT_QUEUE_DEPTH, Overflow, QoS, Reliability};
+use crate::server::serve::{DEFAULT_SERVER_ADDRESS, run_server, server_address};
+use crate::server::shutdown::handle_serve_stop_command;
+use crate::server::status::handle_serve_status_command;
+use crate::topic::clear::handle_topic_clear_command;
+use crate::topic::delete::handle_topic_delete_command;
+use crate::topic::info::handle_topic_info_command;
+use crate::topic::kick::handle_topic_kick_command;
 use crate::topic::list::{handle_topic_list_command};
 use crate::topic::tpub::{handle_topic_pub_command};
 use crate::topic::tsub::{handle_topic_sub_command};
+use crate::service::call::handle_service_call_command;
+use crate::service::info::handle_service_info_command;
+use crate::service::list::handle_service_list_command;
+use crate::action::cancel::handle_action_cancel_command;
+use crate::action::goal::handle_action_send_goal_command;
+use crate::action::list::handle_action_list_command;
+use crate::param::delete::handle_param_delete_command;
+use crate::param::dump::handle_param_dump_command;
+use crate::param::get::handle_param_get_command;
+use crate::param::list::handle_param_list_command;
+use crate::param::load::handle_param_load_command;
+use crate::param::set::handle_param_set_command;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None, arg_required_else_help = true)]
 #[command(propagate_version = true)]
 struct Cli {
+    /// Address of the topics server used by the client commands
+    #[arg(long, global = true, env = "GRF_SERVER", value_name = "host:port", default_value = DEFAULT_SERVER_ADDRESS)]
+    server: String,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -57,6 +85,18 @@ enum  Commands {
     #[command(subcommand)]
     Topic(TopicCommands),
 
+    /// Service interaction commands
+    #[command(subcommand)]
+    Service(ServiceCommands),
+
+    /// Action interaction commands
+    #[command(subcommand)]
+    Action(ActionCommands),
+
+    /// Parameter interaction commands
+    #[command(subcommand)]
+    Param(ParamCommands),
+
     /// Messages interaction commands
     #[command(subcommand)]
     Msg(MsgCommands),
@@ -74,13 +114,41 @@ struct Build {
 
 #[derive(Debug, Args)]
 struct Serve {
-    /// Optional, serve with a specific port
+    /// Optional, serve with a specific port, also the port of the server to stop or query
     #[arg(short, long)]
     port: Option<String>,
 
+    /// Optional, listen on a specific interface address, 127.0.0.1 by default, also the address of the server to stop or query
+    #[arg(long)]
+    bind: Option<String>,
+
     /// Optional, serve a workspace from outside
     #[arg(long)]
-    path: Option<String>
+    path: Option<String>,
+
+    /// Optional, topics to create at startup, see grf-server.toml
+    #[arg(long, value_name = "file")]
+    config: Option<PathBuf>,
+
+    /// Save the topics, retained messages and parameters in the GRF temp folder and restore them on startup
+    #[arg(long)]
+    persist: bool,
+
+    /// Run the server in the background, with its pidfile and log file in the GRF temp folder
+    #[arg(short, long)]
+    daemon: bool,
+
+    #[command(subcommand)]
+    command: Option<ServeCommands>,
+}
+
+#[derive(Debug, Subcommand)]
+enum ServeCommands {
+    /// Stop the server, subscribers get their pending messages before being disconnected
+    Stop,
+
+    /// Show whether a server is running, its uptime, topics and clients counts
+    Status,
 }
 
 #[derive(Debug, Args)]
@@ -127,6 +195,18 @@ enum TopicCommands {
 
     /// Topic list command
     List(ListTopicCommand),
+
+    /// Delete a topic and disconnect its subscribers, system topics cannot be deleted
+    Delete(DeleteTopicCommand),
+
+    /// Drop the retained message of a topic and disconnect its subscribers
+    Clear(ClearTopicCommand),
+
+    /// Disconnect a client from a topic
+    Kick(KickTopicCommand),
+
+    /// Show the subscribers of a topic and the bandwidth used to send them its messages
+    Info(InfoTopicCommand),
 }
 
 #[derive(Debug, Args)]
@@ -138,6 +218,41 @@ struct SubTopicCommand {
     /// Create a topic with given message type, None if no message type was provided
     #[arg(short, long, value_name = "message_type", default_missing_value = None, required = false)]
     create_topic: Option<Option<String>>,
+
+    /// Do not validate the messages published on the created topic on the server side
+    #[arg(long, requires = "create_topic")]
+    no_validation: bool,
+    /// Keep the last message published on the created topic and send it to new subscribers
+    #[arg(short, long, requires = "create_topic")]
+    latched: bool,
+
+    /// Replace the message type of the topic if it exists with another one and has no subscribers
+    #[arg(long, requires = "create_topic")]
+    force_retype: bool,
+
+    /// Amount of messages the server keeps for this subscriber while it is busy
+    #[arg(long, value_name = "depth", default_value_t = DEFAULT_QUEUE_DEPTH)]
+    queue_depth: usize,
+
+    /// What to do with new messages when the queue is full, for best effort subscriptions
+    #[arg(long, value_enum, default_value_t = Overflow::DropOldest)]
+    overflow: Overflow,
+
+    /// Whether messages can be dropped, or publishers must wait for this subscriber
+    #[arg(long, value_enum, default_value_t = Reliability::BestEffort)]
+    reliability: Reliability,
+
+    /// Encoding the server sends the messages in, they are printed as JSON whatever it is
+    #[arg(long, value_enum, default_value_t = Encoding::Json)]
+    encoding: Encoding,
+
+    /// Compression of the messages the server sends, those under 4 KiB are not compressed
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    compression: Compression,
+
+    /// Print the sequence number, publisher and timestamps of the messages, and warn about missed ones
+    #[arg(long)]
+    header: bool,
 }
 
 #[derive(Debug, Args)]
@@ -149,6 +264,14 @@ struct PubTopicCommand {
     /// Message to send
     #[arg(value_name = "message", index = 2)]
     message: Option<String>,
+
+    /// Encoding the message is sent in, the server transcodes it for the subscribers using another one
+    #[arg(long, value_enum, default_value_t = Encoding::Json)]
+    encoding: Encoding,
+
+    /// Compression of the message, when it is over 4 KiB
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    compression: Compression,
 }
 
 #[derive(Debug, Args)]
@@ -156,6 +279,215 @@ struct ListTopicCommand {
     /// Also prints messages types
     #[arg(short, long)]
     message_types: bool,
+
+    /// Also prints whether topics are latched
+    #[arg(short, long)]
+    latched: bool,
+
+    /// Also prints the subscribers and publishers counts
+    #[arg(short, long)]
+    counts: bool,
+}
+
+#[derive(Debug, Args)]
+struct DeleteTopicCommand {
+    /// Name of the topic to delete
+    #[arg(value_name = "topic", index = 1)]
+    topic: String,
+
+    /// Do not ask for a confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[derive(Debug, Args)]
+struct ClearTopicCommand {
+    /// Name of the topic to clear
+    #[arg(value_name = "topic", index = 1)]
+    topic: String,
+
+    /// Do not ask for a confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[derive(Debug, Args)]
+struct KickTopicCommand {
+    /// Name of the topic to kick the client from
+    #[arg(value_name = "topic", index = 1)]
+    topic: String,
+
+    /// Subscriber id, client name, client name and connection number, address or IP, as shown by the info topic
+    #[arg(value_name = "client", index = 2)]
+    client: String,
+}
+
+#[derive(Debug, Args)]
+struct InfoTopicCommand {
+    /// Name of the topic to describe
+    #[arg(value_name = "topic", index = 1)]
+    topic: String,
+}
+
+#[derive(Debug, Subcommand)]
+enum ServiceCommands {
+    /// Service list command
+    List(ListServiceCommand),
+
+    /// Show the message types of the given service
+    Info(InfoServiceCommand),
+
+    /// Call the given service and print its response
+    Call(CallServiceCommand),
+}
+
+#[derive(Debug, Args)]
+struct ListServiceCommand {
+    /// Also prints request and response messages types
+    #[arg(short, long)]
+    message_types: bool,
+}
+
+#[derive(Debug, Args)]
+struct InfoServiceCommand {
+    /// Name of the service
+    #[arg(value_name = "service", index = 1)]
+    service: String,
+}
+
+#[derive(Debug, Args)]
+struct CallServiceCommand {
+    /// Name of the service to call
+    #[arg(value_name = "service", index = 1)]
+    service: String,
+
+    /// Request to send
+    #[arg(value_name = "request", index = 2)]
+    request: Option<String>,
+
+    /// Seconds to wait for the response, at most 300
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..=300))]
+    timeout: u64,
+}
+
+#[derive(Debug, Subcommand)]
+enum ActionCommands {
+    /// Action list command
+    List(ListActionCommand),
+
+    /// Send a goal to the given action and wait for its result
+    SendGoal(SendGoalActionCommand),
+
+    /// Cancel a goal of the given action
+    Cancel(CancelActionCommand),
+}
+
+#[derive(Debug, Args)]
+struct ListActionCommand {
+    /// Also prints goal, feedback and result messages types
+    #[arg(short, long)]
+    message_types: bool,
+}
+
+#[derive(Debug, Args)]
+struct SendGoalActionCommand {
+    /// Name of the action
+    #[arg(value_name = "action", index = 1)]
+    action: String,
+
+    /// Goal to send
+    #[arg(value_name = "goal", index = 2)]
+    goal: Option<String>,
+
+    /// Also prints the feedbacks received until the result
+    #[arg(short, long)]
+    feedback: bool,
+}
+
+#[derive(Debug, Args)]
+struct CancelActionCommand {
+    /// Name of the action
+    #[arg(value_name = "action", index = 1)]
+    action: String,
+
+    /// Identifier of the goal, as printed when it was accepted
+    #[arg(value_name = "goal_id", index = 2)]
+    goal: u64,
+}
+
+#[derive(Debug, Subcommand)]
+enum ParamCommands {
+    /// Print the value of a parameter, or of a group of parameters
+    Get(GetParamCommand),
+
+    /// Set the value of a parameter
+    Set(SetParamCommand),
+
+    /// Delete a parameter, or a group of parameters
+    Delete(DeleteParamCommand),
+
+    /// List the parameters
+    List(ListParamCommand),
+
+    /// Print the parameters as YAML
+    Dump(DumpParamCommand),
+
+    /// Set the parameters of a YAML file
+    Load(LoadParamCommand),
+}
+
+#[derive(Debug, Args)]
+struct GetParamCommand {
+    /// Key of the parameter, as in "robot/arm/max_speed"
+    #[arg(value_name = "key", index = 1)]
+    key: String,
+}
+
+#[derive(Debug, Args)]
+struct SetParamCommand {
+    /// Key of the parameter, as in "robot/arm/max_speed"
+    #[arg(value_name = "key", index = 1)]
+    key: String,
+
+    /// JSON value of the parameter, set as a string if it is not valid JSON
+    #[arg(value_name = "value", index = 2)]
+    value: String,
+}
+
+#[derive(Debug, Args)]
+struct DeleteParamCommand {
+    /// Key of the parameter, as in "robot/arm/max_speed"
+    #[arg(value_name = "key", index = 1)]
+    key: String,
+}
+
+#[derive(Debug, Args)]
+struct ListParamCommand {
+    /// Only list the parameters below this key
+    #[arg(value_name = "key", index = 1)]
+    key: Option<String>,
+
+    /// Also prints the parameters values
+    #[arg(short, long)]
+    values: bool,
+}
+
+#[derive(Debug, Args)]
+struct DumpParamCommand {
+    /// Only dump the parameters below this key
+    #[arg(value_name = "key", index = 1)]
+    key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct LoadParamCommand {
+    /// YAML file to load
+    #[arg(value_name = "file", index = 1)]
+    file: PathBuf,
+
+    /// Key below which the parameters are loaded
+    #[arg(value_name = "key", index = 2)]
+    key: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -215,15 +547,97 @@ fn main() {
         Commands::Topic(topic_commands) => {
             match topic_commands {
                 TopicCommands::Sub(tsub) => {
-                    handle_topic_sub_command(tsub.topic, tsub.create_topic);
+                    let qos = QoS {
+                        depth: tsub.queue_depth,
+                        overflow: tsub.overflow,
+                        reliability: tsub.reliability,
+                    };
+
+                    handle_topic_sub_command(tsub.topic, tsub.create_topic, !tsub.no_validation, tsub.latched, tsub.force_retype, qos, tsub.encoding, tsub.compression, tsub.header, &cli.server);
                 }
 
                 TopicCommands::Pub(mut tpub) => {
-                    handle_topic_pub_command(tpub.topic, tpub.message.take());
+                    handle_topic_pub_command(tpub.topic, tpub.message.take(), tpub.encoding, tpub.compression, &cli.server);
                 }
 
                 TopicCommands::List(list) => {
-                    handle_topic_list_command(list.message_types);
+                    handle_topic_list_command(list.message_types, list.latched, list.counts, &cli.server);
+                }
+
+                TopicCommands::Delete(delete) => {
+                    handle_topic_delete_command(delete.topic, delete.yes, &cli.server);
+                }
+
+                TopicCommands::Clear(clear) => {
+                    handle_topic_clear_command(clear.topic, clear.yes, &cli.server);
+                }
+
+                TopicCommands::Kick(kick) => {
+                    handle_topic_kick_command(kick.topic, kick.client, &cli.server);
+                }
+
+                TopicCommands::Info(info) => {
+                    handle_topic_info_command(info.topic, &cli.server);
+                }
+            }
+        }
+
+        Commands::Service(service_commands) => {
+            match service_commands {
+                ServiceCommands::List(list) => {
+                    handle_service_list_command(list.message_types, &cli.server);
+                }
+
+                ServiceCommands::Info(info) => {
+                    handle_service_info_command(info.service, &cli.server);
+                }
+
+                ServiceCommands::Call(call) => {
+                    handle_service_call_command(call.service, call.request, call.timeout, &cli.server);
+                }
+            }
+        }
+
+        Commands::Action(action_commands) => {
+            match action_commands {
+                ActionCommands::List(list) => {
+                    handle_action_list_command(list.message_types, &cli.server);
+                }
+
+                ActionCommands::SendGoal(send_goal) => {
+                    handle_action_send_goal_command(send_goal.action, send_goal.goal, send_goal.feedback, &cli.server);
+                }
+
+                ActionCommands::Cancel(cancel) => {
+                    handle_action_cancel_command(cancel.action, cancel.goal, &cli.server);
+                }
+            }
+        }
+
+        Commands::Param(param_commands) => {
+            match param_commands {
+                ParamCommands::Get(get) => {
+                    handle_param_get_command(get.key, &cli.server);
+                }
+
+                ParamCommands::Set(set) => {
+                    handle_param_set_command(set.key, set.value, &cli.server);
+                }
+
+                ParamCommands::Delete(delete) => {
+                    handle_param_delete_command(delete.key, &cli.server);
+                }
+
+                ParamCommands::List(list) => {
+                    handle_param_list_command(list.key, list.values, &cli.server);
+                }
+
+                ParamCommands::Dump(dump) => {
+                    handle_param_dump_command(dump.key, &cli.server);
+                }
+
+                ParamCommands::Load(load) => {
+                    handle_param_load_command(load.file, load.key, &cli.server);
                 }
             }
         }
@@ -231,13 +645,13 @@ fn main() {
         Commands::Msg(message_commands) => {
             match message_commands {
                 MsgCommands::Get(get) => {
-                    handle_get_message_command(get.topic);
+                    handle_get_message_command(get.topic, &cli.server);
                 }
                 MsgCommands::Show(show) => {
                     handle_show_message_command(show.message_type, show.pretty)
                 }
                 MsgCommands::Find(find) => {
-                    handle_message_find_command(find.message_type)
+                    handle_message_find_command(find.message_type, &cli.server)
                 }
                 MsgCommands::List(_list) => {
                     handle_message_list_command()
@@ -272,11 +686,29 @@ fn main() {
         }
 
         Commands::Serve(serve) => {
-            run_server(serve.port);
+            // The server given by --port and --bind, if any, rather than the one of --server
+            let server = if serve.port.is_some() || serve.bind.is_some() {
+                server_address(serve.bind.as_deref(), serve.port.as_deref())
+            }
+            else {
+                cli.server.clone()
+            };
+
+            match serve.command {
+                Some(ServeCommands::Stop) => {
+                    handle_serve_stop_command(&server);
+                }
+                Some(ServeCommands::Status) => {
+                    handle_serve_status_command(&server);
+                }
+                None => {
+                    run_server(serve.bind, serve.port, serve.config, serve.persist, serve.daemon);
+                }
+            }
         }
 
         Commands::Completions(completions) => {
-            generate_completions(cmd, cmd_name, completions.no_sourcing);
+            generate_completions(cmd, cmd_name, completions.no_sourcing, &cli.server);
         }
     }
 }
@@ -360,4 +792,19 @@ fn get_temp_folder() -> std::io::Result<String> {
     } else {
         Ok(result.unwrap())
     }
-}
\ No newline at end of file
+}
+
+/// Asks the user to confirm a destructive operation, anything but yes is a refusal
+fn confirm(question: &str) -> bool {
+    println!("{} [yes/no]", question);
+
+    let mut response = String::new();
+
+    if stdin().read_line(&mut response).is_err() {
+        return false;
+    }
+
+    let response = response.trim();
+
+    response == "yes" || response == "y"
+}
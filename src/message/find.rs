@@ -1,12 +1,12 @@
 use crate::message::message::get_topics;
 
 /// Client side find messages type usage
-pub fn handle_message_find_command(message_type: String) {
-    let topics = get_topics();
+pub fn handle_message_find_command(message_type: String, server: &str) {
+    let topics = get_topics(server);
 
     for topic in topics {
-        if topic.1.is_some() && topic.1.unwrap() == message_type {
-            println!("{}", topic.0)
+        if topic.message_type.as_ref() == Some(&message_type) {
+            println!("{}", topic.name)
         }
     }
 }
\ No newline at end of file
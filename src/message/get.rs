@@ -2,8 +2,8 @@ use std::process::exit;
 use crate::message::message::get_message_type;
 
 /// Client side get message
-pub fn handle_get_message_command(topic_name: String) {
-    let message_type = get_message_type(topic_name);
+pub fn handle_get_message_command(topic_name: String, server: &str) {
+    let message_type = get_message_type(topic_name, server);
 
     if message_type.is_some() {
         if message_type.clone().unwrap().is_some() {
@@ -1,18 +1,112 @@
-use std::collections::HashMap;
 use std::{env, fs};
+use std::collections::HashMap;
 use std::fs::File;
+use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use jsonschema::JSONSchema;
 use crate::get_temp_folder;
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use crate::server::protocol::{FrameKind, send_request};
+use crate::server::compression::Compression;
+use crate::server::encoding::Encoding;
+use crate::server::qos::QoS;
+use crate::server::handshake::say_hello;
+use crate::server::serve::{DEFAULT_PORT, topics_file_path};
+use crate::server::transport::open_connection;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Message {
     pub kind: String,
     pub topic: Option<String>,
     pub message_type: Option<String>,
     pub message: Option<Value>,
+    /// Subscriber to remove, only used by unsub requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscriber: Option<u64>,
+    /// Whether the server validates published messages, only used when a topic is created, true by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validation: Option<bool>,
+    /// Whether the topic keeps its last message for new subscribers, only used when a topic is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latched: Option<bool>,
+    /// Whether the message type of an existing topic without subscribers is replaced, only used by sub requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retype: Option<bool>,
+    /// QoS asked by a subscriber, only used by sub requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qos: Option<QoS>,
+    /// Service name, only used by service requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Message type of the service requests, only used by srv requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_type: Option<String>,
+    /// Message type of the service responses, only used by srv requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_type: Option<String>,
+    /// Call being answered, only used by reply requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call: Option<u64>,
+    /// Action name, only used by action requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// Goal the request is about, only used by cancel, feedback and result requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub goal: Option<u64>,
+    /// Message type of the action goals, only used by action requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub goal_type: Option<String>,
+    /// Message type of the action feedbacks, only used by action requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feedback_type: Option<String>,
+    /// Message type of the action results, only used by action requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_type: Option<String>,
+    /// Parameter key, only used by parameter requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Subscriber id or client address, only used by topic_kick requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// Whether a local subscriber reads large messages from shared memory, only used by sub requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_memory: Option<bool>,
+    /// Length of the message a local publisher writes in shared memory instead of sending it, only used by pub requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_memory_length: Option<u64>,
+    /// Encoding of the data frames a subscriber gets, only used by sub requests, JSON by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Encoding>,
+    /// Compression of the large data frames a subscriber gets, only used by sub requests, none by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Whether a subscriber gets the header of the messages along with them, only used by sub requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<bool>,
+    /// When the publisher sent the message, in milliseconds since the Unix epoch, only used by pub requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Seconds the caller waits for the response, only used by call requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+/// Description of a topic, as sent by the server and cached in the topics file
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TopicInfo {
+    pub name: String,
+    pub message_type: Option<String>,
+    /// New subscribers immediately receive the last message published on latched topics
+    #[serde(default)]
+    pub latched: bool,
+    /// Amount of subscribers connected to the topic
+    #[serde(default)]
+    pub subscribers: usize,
+    /// Amount of distinct hosts that published on the topic
+    #[serde(default)]
+    pub publishers: usize,
 }
 
 
@@ -29,17 +123,62 @@ pub fn get_messages_types() -> Vec<String> {
     return messages_types;
 }
 
-pub fn get_topics() -> HashMap<String, Option<String>> {
-    let topics_file_path = PathBuf::from(get_temp_folder().unwrap()).join("topics.json");
+/// Retrieves the topics of the running server, falls back to the topics file if it cannot be reached
+pub fn get_topics(server: &str) -> Vec<TopicInfo> {
+    match request_topics(server) {
+        Ok(topics) => topics,
+        Err(error) => {
+            eprintln!("Warning: could not list the topics of the server at \"{}\" ({}), using the topics file which may be stale", server, error);
+
+            get_cached_topics(server).unwrap_or_else(|error| {
+                eprintln!("Warning: {}, no topics are known", error);
+                vec![]
+            })
+        }
+    }
+}
+
+fn request_topics(server: &str) -> std::io::Result<Vec<TopicInfo>> {
+    let data = Message {
+        kind: String::from("list"),
+        ..Default::default()
+    };
+
+    let mut stream = open_connection(server)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    say_hello(&mut stream)?;
+
+    let response = send_request(&mut stream, &data)?;
 
-    if !topics_file_path.exists() {
-        File::create(&topics_file_path).expect("Cannot create topics file");
+    if response.kind != FrameKind::Ack {
+        return Err(Error::new(ErrorKind::InvalidData, "topics list refused"));
     }
 
-    let topics_file = fs::read_to_string(topics_file_path).expect("Could not read topics file");
-    let topics: HashMap<String, Option<String>> = serde_json::from_str(topics_file.as_str()).unwrap();
+    Ok(response.json()?)
+}
+
+/// Topics written by the server at the given address the last time they changed, empty if there is no topics file
+///
+/// Files written by older servers, mapping the topic names to their message type, are still read
+pub fn get_cached_topics(server: &str) -> Result<Vec<TopicInfo>, String> {
+    let port = server.rsplit_once(':').map_or(DEFAULT_PORT, |(_, port)| port);
+    let topics_file_path = topics_file_path(port);
+
+    let Ok(topics_file) = fs::read_to_string(&topics_file_path) else {
+        return Ok(vec![]);
+    };
 
-    return topics;
+    if let Ok(topics) = serde_json::from_str(&topics_file) {
+        return Ok(topics);
+    }
+
+    serde_json::from_str::<HashMap<String, Option<String>>>(&topics_file)
+        .map(|topics| {
+            topics.into_iter()
+                .map(|(name, message_type)| TopicInfo { name, message_type, ..Default::default() })
+                .collect()
+        })
+        .map_err(|error| format!("could not read the topics file \"{}\" ({})", topics_file_path.display(), error))
 }
 
 pub fn get_schema(message_type: String) -> JSONSchema {
@@ -49,6 +188,21 @@ pub fn get_schema(message_type: String) -> JSONSchema {
     return JSONSchema::compile(&schema).expect("Not a valid schema");
 }
 
+/// Loads the schema of the given message type, returns why it could not be loaded otherwise
+pub fn load_schema(message_type: &str) -> Result<JSONSchema, String> {
+    let schema_file_path = Path::new(get_temp_folder().map_err(|error| error.to_string())?.as_str())
+        .join("schemas")
+        .join(message_type.to_string() + ".json");
+
+    let schema_string = fs::read_to_string(schema_file_path)
+        .map_err(|error| format!("Unable to read schema of message type \"{}\": {}", message_type, error))?;
+    let schema = serde_json::from_str(schema_string.as_str())
+        .map_err(|error| format!("Schema of message type \"{}\" is not valid JSON: {}", message_type, error))?;
+
+    JSONSchema::compile(&schema)
+        .map_err(|error| format!("Schema of message type \"{}\" is not valid: {}", message_type, error))
+}
+
 pub fn get_default(message_type: String) -> Option<String> {
     let message_default_result = fs::read_to_string(
         Path::new(get_temp_folder().unwrap().as_str())
@@ -70,23 +224,10 @@ pub fn get_default(message_type: String) -> Option<String> {
     return Some(message_default);
 }
 
-pub fn get_message_type(topic_name: String) -> Option<Option<String>> {
-    let topics = get_topics();
-
-    return topics.get(topic_name.as_str()).cloned();
-}
+pub fn get_message_type(topic_name: String, server: &str) -> Option<Option<String>> {
+    let topics = get_topics(server);
 
-
-pub fn topic_exists(topic_name: String) -> bool {
-    let topics = get_topics();
-
-    for (topic, _) in topics {
-        if topic == topic_name {
-            return true;
-        }
-    }
-
-    return false;
+    return topics.into_iter().find(|topic| topic.name == topic_name).map(|topic| topic.message_type);
 }
 
 pub fn is_message_type_registered(message_type: String) -> bool {
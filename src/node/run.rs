@@ -1,6 +1,7 @@
 use std::io::{BufRead, BufReader};
 use std::process::{Command, exit, Stdio};
 use crate::node::node::get_nodes;
+use crate::server::handshake::CLIENT_NAME_VARIABLE;
 
 
 pub fn run_node(node_name: String) {
@@ -14,6 +15,7 @@ pub fn run_node(node_name: String) {
                     "--manifest-path", node.package_path.clone().join("Cargo.toml").to_str().unwrap(),
                     "--bin", &node.bin,
                 ])
+                .env(CLIENT_NAME_VARIABLE, &node.name)
                 .stdout(Stdio::piped())
                 .spawn()
                 .unwrap();
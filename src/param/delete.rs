@@ -0,0 +1,59 @@
+use std::net::Shutdown;
+use std::process::exit;
+use crate::message::message::Message;
+use crate::server::param::{AtomicParameters, ChangeKind, ParameterChange, parse_key, publish_change};
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::AtomicTopics;
+use crate::server::transport::Connection;
+
+/// Server side parameter delete, the change is published on the parameters topic
+pub fn handle_message_kind_param_delete(mut stream: Connection, message: Message, parameters: AtomicParameters, topics: AtomicTopics) {
+    let key = message.key.unwrap_or_default();
+
+    let deleted = match parse_key(&key) {
+        Ok(segments) if segments.is_empty() => {
+            Err(Frame::error("invalid_key", "The whole parameters tree cannot be deleted".to_string()))
+        }
+        Ok(segments) => {
+            parameters.delete(&segments)
+                .ok_or_else(|| Frame::error("unknown_parameter", format!("Parameter \"{}\" not found", key)))
+        }
+        Err(error) => Err(Frame::error("invalid_key", error)),
+    };
+
+    match deleted {
+        Ok(_) => {
+            Frame::ack().write_to(&mut stream).ok();
+            println!("Deleted parameter {}", key);
+
+            publish_change(&topics, &ParameterChange {
+                key,
+                change: ChangeKind::Deleted,
+                value: None,
+            });
+        }
+        Err(error) => {
+            error.write_to(&mut stream).ok();
+        }
+    }
+
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side parameter delete
+pub fn handle_param_delete_command(key: String, server: &str) {
+    let data = Message {
+        kind: String::from("param_delete"),
+        key: Some(key),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Could not delete parameter: {}", error.message);
+        exit(1);
+    }
+}
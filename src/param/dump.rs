@@ -0,0 +1,8 @@
+use crate::param::get::get_parameter;
+
+/// Client side parameter dump, prints the parameters below the given key as YAML
+pub fn handle_param_dump_command(key: Option<String>, server: &str) {
+    let parameters = get_parameter(&key.unwrap_or_default(), server);
+
+    print!("{}", serde_yaml::to_string(&parameters).expect("Could not convert the parameters to YAML"));
+}
@@ -0,0 +1,50 @@
+use std::net::Shutdown;
+use std::process::exit;
+use serde_json::Value;
+use crate::message::message::Message;
+use crate::server::param::{AtomicParameters, parse_key};
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::transport::Connection;
+
+/// Server side parameter get, the whole subtree is sent for a group of parameters
+pub fn handle_message_kind_param_get(mut stream: Connection, message: Message, parameters: AtomicParameters) {
+    let key = message.key.unwrap_or_default();
+
+    let response = match parse_key(&key) {
+        Ok(segments) => match parameters.get(&segments) {
+            Some(value) => Frame::ack_with(&value),
+            None => Frame::error("unknown_parameter", format!("Parameter \"{}\" not found", key)),
+        },
+        Err(error) => Frame::error("invalid_key", error),
+    };
+
+    response.write_to(&mut stream).ok();
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Retrieves a parameter or a group of parameters, exits if it does not exist
+pub fn get_parameter(key: &str, server: &str) -> Value {
+    let data = Message {
+        kind: String::from("param_get"),
+        key: Some(key.to_string()),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("{}", error.message);
+        exit(1);
+    }
+
+    response.json().expect("Malformed parameter value")
+}
+
+/// Client side parameter get
+pub fn handle_param_get_command(key: String, server: &str) {
+    let value = get_parameter(&key, server);
+
+    println!("{}", serde_json::to_string_pretty(&value).unwrap());
+}
@@ -0,0 +1,44 @@
+use serde_json::Value;
+use crate::param::get::get_parameter;
+use crate::server::param::flatten_parameters;
+
+/// Client side parameter list, prints every parameter below the given key
+pub fn handle_param_list_command(key: Option<String>, with_values: bool, server: &str) {
+    let key = key.unwrap_or_default();
+    let parameters = flatten_parameters(key.trim_matches('/'), &get_parameter(&key, server));
+
+    let mut separator = "------------------------------".to_string();
+
+    print!("{0: <30}{1: <10}", "Parameter", "Type");
+    separator += "----------";
+
+    if with_values {
+        print!("Value");
+        separator += "--------------------";
+    }
+
+    println!();
+    println!("{separator}");
+
+    for (parameter, value) in parameters {
+        print!("{0: <30}{1: <10}", parameter, value_type(&value));
+
+        if with_values {
+            print!("{}", value);
+        }
+
+        println!();
+    }
+}
+
+/// Name of the JSON type of a value
+fn value_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
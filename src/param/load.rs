@@ -0,0 +1,88 @@
+use std::fs;
+use std::net::Shutdown;
+use std::path::PathBuf;
+use std::process::exit;
+use serde_json::Value;
+use crate::message::message::Message;
+use crate::server::param::{AtomicParameters, ChangeKind, flatten_parameters, ParameterChange, parse_key, publish_change};
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::AtomicTopics;
+use crate::server::transport::Connection;
+
+/// Server side parameters load, every parameter of the tree is set and its change published, or none if one cannot be
+pub fn handle_message_kind_param_load(mut stream: Connection, message: Message, parameters: AtomicParameters, topics: AtomicTopics) {
+    let prefix = message.key.unwrap_or_default();
+
+    let Some(Value::Object(tree)) = message.message else {
+        Frame::error("invalid_parameters", "Loaded parameters must be a map".to_string()).write_to(&mut stream).ok();
+        return;
+    };
+
+    let loaded = flatten_parameters(prefix.trim_matches('/'), &Value::Object(tree));
+
+    // Every key is checked before any parameter is set, a document is loaded whole or not at all
+    let (keys, errors): (Vec<_>, Vec<_>) = loaded.iter()
+        .map(|(key, value)| parse_key(key).map(|segments| (segments, value.clone())))
+        .partition(Result::is_ok);
+    let errors: Vec<String> = errors.into_iter().filter_map(Result::err).collect();
+
+    let result = if errors.is_empty() {
+        parameters.set_all(keys.into_iter().filter_map(Result::ok).collect())
+    }
+    else {
+        Err(errors)
+    };
+
+    if let Err(errors) = result {
+        let error = format!("{} parameters could not be set, none was loaded", errors.len());
+        Frame::error_with_details("invalid_key", error, errors).write_to(&mut stream).ok();
+        stream.shutdown(Shutdown::Both).ok();
+        return;
+    }
+
+    Frame::ack().write_to(&mut stream).ok();
+    stream.shutdown(Shutdown::Both).ok();
+    println!("Loaded {} parameters", loaded.len());
+
+    for (key, value) in loaded {
+        publish_change(&topics, &ParameterChange {
+            key,
+            change: ChangeKind::Set,
+            value: Some(value),
+        });
+    }
+}
+
+/// Client side parameters load from a YAML file
+pub fn handle_param_load_command(file: PathBuf, key: Option<String>, server: &str) {
+    let content = fs::read_to_string(&file).unwrap_or_else(|error| {
+        println!("Could not read \"{}\": {}", file.display(), error);
+        exit(1);
+    });
+
+    let parameters: Value = serde_yaml::from_str(&content).unwrap_or_else(|error| {
+        println!("Could not parse \"{}\": {}", file.display(), error);
+        exit(1);
+    });
+
+    let data = Message {
+        kind: String::from("param_load"),
+        key,
+        message: Some(parameters),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Could not load parameters: {}", error.message);
+
+        for detail in error.details {
+            println!("  - {}", detail);
+        }
+
+        exit(1);
+    }
+}
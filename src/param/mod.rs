@@ -0,0 +1,6 @@
+pub mod get;
+pub mod set;
+pub mod delete;
+pub mod list;
+pub mod dump;
+pub mod load;
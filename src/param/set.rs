@@ -0,0 +1,55 @@
+use std::net::Shutdown;
+use std::process::exit;
+use serde_json::Value;
+use crate::message::message::Message;
+use crate::server::param::{AtomicParameters, ChangeKind, ParameterChange, parse_key, publish_change};
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::AtomicTopics;
+use crate::server::transport::Connection;
+
+/// Server side parameter set, the change is published on the parameters topic
+pub fn handle_message_kind_param_set(mut stream: Connection, message: Message, parameters: AtomicParameters, topics: AtomicTopics) {
+    let key = message.key.unwrap_or_default();
+    let value = message.message.unwrap_or(Value::Null);
+
+    let result = parse_key(&key).and_then(|segments| parameters.set(&segments, value.clone()));
+
+    match result {
+        Ok(()) => {
+            Frame::ack().write_to(&mut stream).ok();
+            println!("Set parameter {}", key);
+
+            publish_change(&topics, &ParameterChange {
+                key,
+                change: ChangeKind::Set,
+                value: Some(value),
+            });
+        }
+        Err(error) => {
+            Frame::error("invalid_key", error).write_to(&mut stream).ok();
+        }
+    }
+
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side parameter set, values that are not valid JSON are set as strings
+pub fn handle_param_set_command(key: String, value: String, server: &str) {
+    let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+
+    let data = Message {
+        kind: String::from("param_set"),
+        key: Some(key),
+        message: Some(value),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Could not set parameter: {}", error.message);
+        exit(1);
+    }
+}
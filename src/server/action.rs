@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::server::protocol::Frame;
+use crate::server::qos::{OutboundQueue, QoS};
+use crate::server::service::load_optional_schema;
+use crate::server::transport::Connection;
+
+static NEXT_GOAL_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Description of an action, as sent to the clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionInfo {
+    pub name: String,
+    pub goal_type: Option<String>,
+    pub feedback_type: Option<String>,
+    pub result_type: Option<String>,
+}
+
+/// Goal or cancellation forwarded by the server to an action provider
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionGoal {
+    pub goal: u64,
+    /// The provider should stop working on the goal and send a canceled result
+    pub cancel: bool,
+    pub message: Option<Value>,
+}
+
+/// State of a goal
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GoalStatus {
+    /// The goal is being worked on, the message is a feedback
+    Active,
+    /// The goal was reached, the message is the result
+    Succeeded,
+    /// The goal was canceled before being reached
+    Canceled,
+    /// The provider gave up on the goal
+    Aborted,
+}
+
+/// Update sent by the server to the client that submitted a goal
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GoalUpdate {
+    pub goal: u64,
+    pub status: GoalStatus,
+    pub message: Option<Value>,
+}
+
+/// Response to an accepted goal
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GoalAccepted {
+    /// Identifier to give in order to cancel the goal
+    pub goal: u64,
+}
+
+/// Action advertised by a node, the goals are forwarded through its connection
+pub struct Action {
+    pub info: ActionInfo,
+    pub goal_schema: Option<Arc<JSONSchema>>,
+    pub feedback_schema: Option<Arc<JSONSchema>>,
+    pub result_schema: Option<Arc<JSONSchema>>,
+    pub provider: Arc<Mutex<Connection>>,
+}
+
+/// Goal waiting for its result, its updates are written to the client by a writer thread
+pub struct ActiveGoal {
+    pub action: String,
+    pub caller: Arc<Mutex<Connection>>,
+    pub queue: Arc<OutboundQueue>,
+}
+
+#[derive(Clone, Default)]
+pub struct AtomicActions {
+    pub(crate) actions: Arc<Mutex<Vec<Action>>>,
+    pub(crate) goals: Arc<Mutex<HashMap<u64, ActiveGoal>>>,
+}
+
+impl GoalUpdate {
+    /// Data frame carrying the update
+    pub fn to_frame(&self) -> Frame {
+        Frame::data(serde_json::to_vec(self).unwrap())
+    }
+}
+
+impl Action {
+    /// Creates an action validating its goals, feedbacks and results against the schemas of their message types
+    pub fn new(info: ActionInfo, provider: Connection) -> Result<Action, String> {
+        Ok(Action {
+            goal_schema: load_optional_schema(info.goal_type.as_ref())?,
+            feedback_schema: load_optional_schema(info.feedback_type.as_ref())?,
+            result_schema: load_optional_schema(info.result_type.as_ref())?,
+            info,
+            provider: Arc::new(Mutex::new(provider)),
+        })
+    }
+}
+
+impl AtomicActions {
+    /// Locks the actions list, the lock should be released before doing any network I/O
+    pub fn lock(&self) -> MutexGuard<'_, Vec<Action>> {
+        self.actions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Keeps the caller connection until the goal is over and acknowledges the goal,
+    /// returns the goal identifier or None if the action is no longer advertised
+    pub fn add_goal(&self, action: &str, caller: Connection) -> Option<u64> {
+        let goal = NEXT_GOAL_ID.fetch_add(1, Ordering::Relaxed);
+
+        // The client must know the goal before any feedback arrives
+        let queue = Arc::new(OutboundQueue::new(QoS::default()));
+        queue.push(Arc::new(Frame::ack_with(&GoalAccepted { goal }).to_bytes()));
+
+        let caller = Arc::new(Mutex::new(caller));
+
+        {
+            // Checked under the actions lock, a provider leaving afterwards sees the goal and aborts it
+            let actions = self.lock();
+
+            if !actions.iter().any(|advertised| advertised.info.name == action) {
+                return None;
+            }
+
+            self.goals.lock().unwrap_or_else(PoisonError::into_inner).insert(goal, ActiveGoal {
+                action: action.to_string(),
+                caller: Arc::clone(&caller),
+                queue: Arc::clone(&queue),
+            });
+        }
+
+        self.start_goal_writer(action.to_string(), goal, caller, queue);
+
+        Some(goal)
+    }
+
+    /// Writes the updates of a goal to its client and watches its connection, the goal is canceled if the client leaves before its end
+    fn start_goal_writer(&self, action: String, goal: u64, caller: Arc<Mutex<Connection>>, queue: Arc<OutboundQueue>) {
+        let watched = caller.lock().unwrap_or_else(PoisonError::into_inner).try_clone();
+
+        match watched {
+            Ok(mut watched) => {
+                let actions = self.clone();
+                let action = action.clone();
+
+                // Goal clients send nothing once their goal is accepted, the end of their connection means they left
+                thread::spawn(move || {
+                    let mut buffer = [0u8; 64];
+
+                    loop {
+                        match watched.read(&mut buffer) {
+                            Ok(0) => break,
+                            Ok(_) => {}
+                            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                            Err(_) => break,
+                        }
+                    }
+
+                    actions.abandon_goal(&action, goal);
+                });
+            }
+            Err(error) => println!("Could not watch the client of goal {}, it is only seen leaving on the next update: {}", goal, error),
+        }
+
+        let actions = self.clone();
+
+        thread::spawn(move || {
+            while let Some(frame) = queue.pop() {
+                let connection = caller.lock().unwrap_or_else(PoisonError::into_inner);
+
+                if (&*connection).write_all(&frame).is_err() {
+                    break;
+                }
+            }
+
+            queue.close();
+            caller.lock().unwrap_or_else(PoisonError::into_inner).shutdown(Shutdown::Both).ok();
+
+            actions.abandon_goal(&action, goal);
+        });
+    }
+
+    /// Cancels a goal whose client left before its end, the goals that are over are already forgotten
+    fn abandon_goal(&self, action: &str, goal: u64) {
+        let Some(active_goal) = self.goals.lock().unwrap_or_else(PoisonError::into_inner).remove(&goal) else {
+            return;
+        };
+
+        active_goal.queue.close();
+        active_goal.caller.lock().unwrap_or_else(PoisonError::into_inner).shutdown(Shutdown::Both).ok();
+
+        self.forward_cancel(action, goal);
+        println!("Client of goal {} of action {} left, the goal is canceled", goal, action);
+    }
+
+    /// Whether the given goal of the given action is still active
+    pub fn has_goal(&self, action: &str, goal: u64) -> bool {
+        self.goals.lock().unwrap_or_else(PoisonError::into_inner)
+            .get(&goal)
+            .is_some_and(|active_goal| active_goal.action == action)
+    }
+
+    /// Asks the provider of an action to stop working on a goal, returns false if it could not be reached
+    pub fn forward_cancel(&self, action: &str, goal: u64) -> bool {
+        let provider = self.lock()
+            .iter()
+            .find(|advertised| advertised.info.name == action)
+            .map(|advertised| Arc::clone(&advertised.provider));
+
+        let Some(provider) = provider else {
+            return false;
+        };
+
+        let action_goal = ActionGoal {
+            goal,
+            cancel: true,
+            message: None,
+        };
+
+        let forwarded = Frame::data(serde_json::to_vec(&action_goal).unwrap())
+            .write_to(&mut *provider.lock().unwrap_or_else(PoisonError::into_inner))
+            .is_ok();
+
+        forwarded
+    }
+
+    /// Queues a feedback for the client waiting for the goal, returns false if the goal is not active
+    ///
+    /// The provider reader never waits for a slow client, the oldest feedbacks are dropped instead
+    pub fn send_feedback(&self, action: &str, goal: u64, feedback: Option<Value>) -> bool {
+        let queue = self.goals.lock().unwrap_or_else(PoisonError::into_inner)
+            .get(&goal)
+            .filter(|active_goal| active_goal.action == action)
+            .map(|active_goal| Arc::clone(&active_goal.queue));
+
+        let Some(queue) = queue else {
+            return false;
+        };
+
+        let update = GoalUpdate {
+            goal,
+            status: GoalStatus::Active,
+            message: feedback,
+        };
+
+        queue.push(Arc::new(update.to_frame().to_bytes()));
+
+        true
+    }
+
+    /// Queues the last frame of a goal for its client and forgets it, returns false if the goal is not active
+    pub fn finish_goal(&self, action: &str, goal: u64, frame: Frame) -> bool {
+        let active_goal = {
+            let mut goals = self.goals.lock().unwrap_or_else(PoisonError::into_inner);
+
+            match goals.get(&goal) {
+                Some(active_goal) if active_goal.action == action => goals.remove(&goal),
+                _ => None,
+            }
+        };
+
+        let Some(active_goal) = active_goal else {
+            return false;
+        };
+
+        // The writer closes the client connection once the frame is written
+        active_goal.queue.finish(Arc::new(frame.to_bytes()));
+
+        true
+    }
+
+    /// Tells the providers and the goal clients that the server is shutting down
+    pub fn close_all(&self, reason: &str) {
+        let providers: Vec<Arc<Mutex<Connection>>> = self.lock()
+            .iter()
+            .map(|action| Arc::clone(&action.provider))
+            .collect();
+
+        for provider in providers {
+            let mut provider = provider.lock().unwrap_or_else(PoisonError::into_inner);
+            Frame::close(reason).write_to(&mut *provider).ok();
+            provider.shutdown(Shutdown::Both).ok();
+        }
+
+        let goals: Vec<ActiveGoal> = self.goals.lock().unwrap_or_else(PoisonError::into_inner)
+            .drain()
+            .map(|(_, active_goal)| active_goal)
+            .collect();
+
+        for active_goal in goals {
+            // Closed under the caller lock, the writer cannot close the connection before the error is written
+            let mut caller = active_goal.caller.lock().unwrap_or_else(PoisonError::into_inner);
+            active_goal.queue.close();
+            let error = format!("Server is shutting down: {}", reason);
+            Frame::error("server_shutdown", error).write_to(&mut *caller).ok();
+            caller.shutdown(Shutdown::Both).ok();
+        }
+    }
+
+    /// Unregisters an action whose provider left, its goals are aborted
+    pub fn remove_action(&self, name: &str) {
+        self.lock().retain(|action| action.info.name != name);
+
+        let goals: Vec<u64> = self.goals.lock().unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(_, active_goal)| active_goal.action == name)
+            .map(|(goal, _)| *goal)
+            .collect();
+
+        for goal in goals {
+            let update = GoalUpdate {
+                goal,
+                status: GoalStatus::Aborted,
+                message: None,
+            };
+
+            self.finish_goal(name, goal, update.to_frame());
+        }
+    }
+}
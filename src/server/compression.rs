@@ -0,0 +1,190 @@
+use std::convert::TryInto;
+use std::io::Read;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::server::protocol::MAX_PAYLOAD_LENGTH;
+
+/// Bits of the frame flags giving the compression of the payload
+pub const FLAG_COMPRESSION_MASK: u8 = 0b0001_1000;
+
+/// Payloads smaller than this are sent as they are, compressing them would not save much
+pub const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// Level used by zstd, fast enough for messages sent on the fly
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression of the frame payloads, lz4 is faster while zstd compresses more
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression bits of the frame flags
+    pub fn flag(self) -> u8 {
+        match self {
+            Compression::None => 0b0000_0000,
+            Compression::Lz4 => 0b0000_1000,
+            Compression::Zstd => 0b0001_0000,
+        }
+    }
+
+    /// Compression of a frame payload, None if the flags name an unknown one
+    pub fn from_flags(flags: u8) -> Option<Compression> {
+        match flags & FLAG_COMPRESSION_MASK {
+            0b0000_0000 => Some(Compression::None),
+            0b0000_1000 => Some(Compression::Lz4),
+            0b0001_0000 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compression actually used for a payload, small ones are not compressed
+    pub fn for_payload(self, payload: &[u8]) -> Compression {
+        if payload.len() < COMPRESSION_THRESHOLD {
+            Compression::None
+        }
+        else {
+            self
+        }
+    }
+
+    pub fn compress(self, payload: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => payload.to_vec(),
+            Compression::Lz4 => lz4_flex::compress_prepend_size(payload),
+            Compression::Zstd => zstd::bulk::compress(payload, ZSTD_LEVEL).unwrap(),
+        }
+    }
+
+    /// Restores a compressed payload, refusing to inflate it over the payload limit
+    pub fn decompress(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Compression::None => Ok(payload.to_vec()),
+            Compression::Lz4 => {
+                let length = payload.get(..4)
+                    .map(|length| u32::from_le_bytes(length.try_into().unwrap()) as u64)
+                    .ok_or("Truncated lz4 payload")?;
+
+                if length > MAX_PAYLOAD_LENGTH {
+                    return Err(format!("Decompressed payload of {} bytes is over the {} bytes limit", length, MAX_PAYLOAD_LENGTH));
+                }
+
+                lz4_flex::decompress_size_prepended(payload).map_err(|error| error.to_string())
+            }
+            Compression::Zstd => {
+                let decoder = zstd::stream::read::Decoder::new(payload).map_err(|error| error.to_string())?;
+
+                let mut decompressed = vec![];
+                decoder.take(MAX_PAYLOAD_LENGTH + 1).read_to_end(&mut decompressed).map_err(|error| error.to_string())?;
+
+                if decompressed.len() as u64 > MAX_PAYLOAD_LENGTH {
+                    return Err(format!("Decompressed payload is over the {} bytes limit", MAX_PAYLOAD_LENGTH));
+                }
+
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use serde_json::json;
+    use crate::message::message::Message;
+    use crate::server::protocol::{Frame, FrameKind, PROTOCOL_VERSION, ProtocolError, read_request};
+    use crate::server::testing::{frame_bytes, Rng, valid_request};
+    use super::*;
+
+    /// Zstd payload of a few KiB inflating to the given amount of zeros, written without holding them in memory
+    fn zstd_bomb(length: u64) -> Vec<u8> {
+        let mut encoder = zstd::stream::write::Encoder::new(vec![], ZSTD_LEVEL).unwrap();
+        let chunk = vec![0u8; 1 << 20];
+
+        for _ in 0..length / chunk.len() as u64 {
+            encoder.write_all(&chunk).unwrap();
+        }
+
+        encoder.write_all(&chunk[..(length % chunk.len() as u64) as usize]).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compressed_requests_are_restored() {
+        let mut rng = Rng(0xBF58476D1CE4E5B9);
+
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            for _ in 0..200 {
+                let mut request = valid_request(&mut rng, "pub");
+                request["message"] = json!(rng.string().repeat(1 + rng.below(1_000)));
+
+                let message: Message = serde_json::from_value(request).unwrap();
+                let frame = Frame::request(&message).compressed(compression);
+
+                if frame.payload.len() >= COMPRESSION_THRESHOLD {
+                    assert_eq!(Compression::from_flags(frame.flags), Some(compression));
+                }
+
+                let bytes = frame.to_bytes();
+                let parsed = read_request(&mut bytes.as_slice()).unwrap().unwrap();
+
+                assert_eq!(serde_json::to_value(&parsed).unwrap(), serde_json::to_value(&message).unwrap());
+            }
+        }
+
+        let frame = Frame::request(&Message::default()).compressed(Compression::Zstd);
+        assert_eq!(frame.flags, 0, "Small payloads are sent as they are");
+
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let mut bytes = frame_bytes(PROTOCOL_VERSION, FrameKind::Request as u8, b"not compressed at all");
+            bytes[2] = compression.flag();
+            assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::DecompressionFailed(_))));
+        }
+
+        let mut bytes = Frame::request(&Message::default()).to_bytes();
+        bytes[2] = FLAG_COMPRESSION_MASK;
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::UnknownCompression(_))));
+    }
+
+    #[test]
+    fn zstd_bombs_are_stopped_at_the_payload_limit() {
+        let bomb = zstd_bomb(4 * MAX_PAYLOAD_LENGTH);
+        assert!((bomb.len() as u64) < MAX_PAYLOAD_LENGTH, "The bomb fits in a frame");
+
+        let error = Compression::Zstd.decompress(&bomb).unwrap_err();
+        assert!(error.contains("over the"), "{}", error);
+
+        let restored = Compression::Zstd.decompress(&zstd_bomb(MAX_PAYLOAD_LENGTH)).unwrap();
+        assert_eq!(restored.len() as u64, MAX_PAYLOAD_LENGTH, "Payloads right at the limit are restored");
+
+        let mut bytes = frame_bytes(PROTOCOL_VERSION, FrameKind::Request as u8, &bomb);
+        bytes[2] = Compression::Zstd.flag();
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::DecompressionFailed(_))));
+    }
+
+    #[test]
+    fn lz4_bombs_are_stopped_at_the_payload_limit() {
+        let bomb = Compression::Lz4.compress(&vec![0u8; MAX_PAYLOAD_LENGTH as usize + 1]);
+        assert!((bomb.len() as u64) < MAX_PAYLOAD_LENGTH, "The bomb fits in a frame");
+        assert!(Compression::Lz4.decompress(&bomb).is_err());
+
+        // A size prefix over the limit is refused before anything is allocated
+        let mut oversized = Compression::Lz4.compress(&[1, 2, 3]);
+        oversized[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Compression::Lz4.decompress(&oversized).unwrap_err().contains("over the"));
+
+        // A size prefix lying about a block inflating further is caught by the decoder
+        let mut understated = Compression::Lz4.compress(&[0u8; 1 << 20]);
+        understated[..4].copy_from_slice(&16u32.to_le_bytes());
+        assert!(Compression::Lz4.decompress(&understated).is_err());
+
+        let mut bytes = frame_bytes(PROTOCOL_VERSION, FrameKind::Request as u8, &bomb);
+        bytes[2] = Compression::Lz4.flag();
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::DecompressionFailed(_))));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use serde::Deserialize;
+use crate::message::message::is_message_type_registered;
+use crate::server::qos::QoS;
+use crate::server::topic::{SYSTEM_TOPICS, Topic, TopicAccess};
+
+/// Configuration given to `grf serve --config`
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Topics created when the server starts
+    #[serde(default)]
+    pub topics: Vec<TopicConfig>,
+}
+
+/// Topic declared in the configuration
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TopicConfig {
+    pub name: String,
+    /// Registered message type of the topic, untyped if absent
+    pub message_type: Option<String>,
+    #[serde(default)]
+    pub latched: bool,
+    /// Whether published messages are validated against the schema of the message type
+    #[serde(default = "default_validation")]
+    pub validation: bool,
+    /// QoS granted to every subscriber of the topic, instead of the one they ask for
+    pub qos: Option<QoS>,
+    #[serde(default)]
+    pub access: AccessConfig,
+}
+
+/// Hosts allowed to use a topic, by IP address
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AccessConfig {
+    /// Hosts allowed to publish, everyone if absent
+    pub publish: Option<Vec<String>>,
+    /// Hosts allowed to subscribe, everyone if absent
+    pub subscribe: Option<Vec<String>>,
+}
+
+fn default_validation() -> bool {
+    true
+}
+
+/// Reads the configuration file
+pub fn load_server_config(path: &Path) -> Result<ServerConfig, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read \"{}\": {}", path.display(), error))?;
+
+    toml::from_str(&content)
+        .map_err(|error| format!("Malformed configuration \"{}\": {}", path.display(), error))
+}
+
+impl ServerConfig {
+    /// Topics declared in the configuration, checked against the system topics and the message types registry
+    pub fn declared_topics(&self) -> Result<Vec<Topic>, Vec<String>> {
+        let mut names = HashSet::new();
+        let mut topics = vec![];
+        let mut errors = vec![];
+
+        for topic_config in &self.topics {
+            if SYSTEM_TOPICS.contains(&topic_config.name.as_str()) {
+                errors.push(format!("Topic \"{}\" is a system topic and cannot be declared", topic_config.name));
+            }
+            else if !names.insert(topic_config.name.as_str()) {
+                errors.push(format!("Topic \"{}\" is declared more than once", topic_config.name));
+            }
+
+            match topic_config.to_topic() {
+                Ok(topic) => topics.push(topic),
+                Err(topic_errors) => errors.extend(topic_errors),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(topics)
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+impl TopicConfig {
+    fn to_topic(&self) -> Result<Topic, Vec<String>> {
+        let mut errors = vec![];
+
+        let topic = match &self.message_type {
+            Some(message_type) if !is_message_type_registered(message_type.clone()) => {
+                errors.push(format!("Message type \"{}\" of topic \"{}\" has not been registered", message_type, self.name));
+                None
+            }
+            Some(message_type) if self.validation => {
+                Topic::with_validation(&self.name, message_type.clone())
+                    .map_err(|error| errors.push(error))
+                    .ok()
+            }
+            message_type => Some(Topic::new(&self.name, message_type.clone())),
+        };
+
+        let access = TopicAccess {
+            publishers: self.parse_hosts(self.access.publish.as_ref(), &mut errors),
+            subscribers: self.parse_hosts(self.access.subscribe.as_ref(), &mut errors),
+        };
+
+        match topic {
+            Some(mut topic) if errors.is_empty() => {
+                topic.qos = self.qos.map(|qos| QoS::negotiate(Some(qos)));
+                topic.access = access;
+                topic.declared = true;
+
+                Ok(topic.latched(self.latched))
+            }
+            _ => Err(errors),
+        }
+    }
+
+    fn parse_hosts(&self, hosts: Option<&Vec<String>>, errors: &mut Vec<String>) -> Option<HashSet<IpAddr>> {
+        let hosts = hosts?;
+
+        Some(hosts.iter()
+            .filter_map(|host| {
+                host.parse()
+                    .map_err(|_| errors.push(format!("Host \"{}\" of topic \"{}\" is not an IP address", host, self.name)))
+                    .ok()
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::qos::MAX_QUEUE_DEPTH;
+    use super::*;
+
+    fn declared_topics(config: &str) -> Result<Vec<Topic>, Vec<String>> {
+        toml::from_str::<ServerConfig>(config).unwrap().declared_topics()
+    }
+
+    #[test]
+    fn declared_topics_are_created_with_their_settings() {
+        let topics = declared_topics(r#"
+            [[topics]]
+            name = "pose"
+            latched = true
+            qos = { depth = 100000, reliability = "reliable" }
+            access = { publish = ["127.0.0.1", "::1"] }
+
+            [[topics]]
+            name = "logs"
+        "#).unwrap();
+
+        assert_eq!(topics.len(), 2);
+        assert!(topics.iter().all(|topic| topic.declared && topic.message_type.is_none()));
+
+        let pose = &topics[0];
+        assert!(pose.latched);
+        assert_eq!(pose.qos.map(|qos| qos.depth), Some(MAX_QUEUE_DEPTH));
+        assert_eq!(pose.access.publishers.as_ref().map(|hosts| hosts.len()), Some(2));
+        assert!(pose.access.subscribers.is_none());
+
+        let logs = &topics[1];
+        assert!(!logs.latched && logs.qos.is_none() && logs.access.publishers.is_none());
+    }
+
+    #[test]
+    fn system_and_duplicate_topics_are_refused() {
+        let errors = declared_topics(r#"
+            [[topics]]
+            name = "parameters"
+
+            [[topics]]
+            name = "pose"
+
+            [[topics]]
+            name = "pose"
+        "#).err().unwrap();
+
+        assert_eq!(errors, vec![
+            "Topic \"parameters\" is a system topic and cannot be declared".to_string(),
+            "Topic \"pose\" is declared more than once".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn invalid_hosts_are_refused() {
+        let errors = declared_topics(r#"
+            [[topics]]
+            name = "pose"
+            access = { publish = ["127.0.0.1"], subscribe = ["localhost", "10.0.0.256"] }
+        "#).err().unwrap();
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("\"localhost\""));
+        assert!(errors[1].contains("\"10.0.0.256\""));
+    }
+
+    #[test]
+    fn message_types_must_be_registered() {
+        let temp_folder = std::env::temp_dir().join(format!("grf-config-tests-{}", std::process::id()));
+        fs::create_dir_all(&temp_folder).unwrap();
+        fs::write(temp_folder.join("messages_types.json"), r#"["Point"]"#).unwrap();
+        std::env::set_var("GRF_TEMP_FOLDER", &temp_folder);
+
+        let topics = declared_topics(r#"
+            [[topics]]
+            name = "pose"
+            message_type = "Point"
+            validation = false
+        "#).unwrap();
+        assert_eq!(topics[0].message_type.as_deref(), Some("Point"));
+
+        let errors = declared_topics(r#"
+            [[topics]]
+            name = "pose"
+            message_type = "Pose"
+        "#).err().unwrap();
+        assert_eq!(errors, vec!["Message type \"Pose\" of topic \"pose\" has not been registered".to_string()]);
+
+        fs::remove_dir_all(&temp_folder).unwrap();
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        assert!(toml::from_str::<ServerConfig>("[[topics]]\nname = \"pose\"\nlatch = true").is_err());
+        assert!(toml::from_str::<ServerConfig>("[[topics]]\nname = \"pose\"\nqos = { depth = 1, overflow = \"drop_all\" }").is_err());
+    }
+}
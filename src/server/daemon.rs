@@ -0,0 +1,86 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Command, exit, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::get_temp_folder;
+
+/// How long to wait for a daemon to accept connections before giving up
+const DAEMON_START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pidfile of the server listening on the given port
+pub fn pid_file_path(port: &str) -> PathBuf {
+    PathBuf::from(get_temp_folder().unwrap()).join(format!("grf-{}.pid", port))
+}
+
+/// Log file of the server running in the background on the given port
+pub fn log_file_path(port: &str) -> PathBuf {
+    PathBuf::from(get_temp_folder().unwrap()).join(format!("grf-{}.log", port))
+}
+
+/// Starts the server again in the background with the given arguments, returns once it accepts connections
+pub fn spawn_daemon(port: &str, address: &str, args: &[String]) {
+    let log_path = log_file_path(port);
+
+    let log = OpenOptions::new().create(true).append(true).open(&log_path).unwrap_or_else(|error| {
+        println!("Could not open the log file \"{}\": {}", log_path.display(), error);
+        exit(1);
+    });
+
+    let mut command = Command::new(env::current_exe().expect("Could not find the grf executable"));
+    command.args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().expect("Could not open the log file"))
+        .stderr(log);
+
+    detach(&mut command);
+
+    // The daemon outlives this process, it is never waited for once started
+    #[allow(clippy::zombie_processes)]
+    let mut child = command.spawn().unwrap_or_else(|error| {
+        println!("Could not start the server: {}", error);
+        exit(1);
+    });
+
+    let deadline = Instant::now() + DAEMON_START_TIMEOUT;
+
+    loop {
+        if TcpStream::connect(address).is_ok() {
+            println!("Server started in the background on {} with pid {}", address, child.id());
+            println!("Logs are written to \"{}\"", log_path.display());
+            return;
+        }
+
+        if let Ok(Some(status)) = child.try_wait() {
+            println!("Server exited with {}, see \"{}\"", status, log_path.display());
+            exit(1);
+        }
+
+        if Instant::now() > deadline {
+            println!("Server did not start in time, see \"{}\"", log_path.display());
+            exit(1);
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Keeps the daemon out of the terminal process group, so that it does not get its signals
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0);
+}
+
+#[cfg(windows)]
+fn detach(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+    const DETACHED_PROCESS: u32 = 0x00000008;
+
+    command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
@@ -0,0 +1,132 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Bits of the frame flags giving the encoding of the payload
+pub const FLAG_ENCODING_MASK: u8 = 0b0000_0110;
+
+/// Format of the frame payloads, JSON stays readable while the binary formats are more compact
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    #[serde(rename = "msgpack")]
+    #[value(name = "msgpack")]
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    /// Encoding bits of the frame flags
+    pub fn flag(self) -> u8 {
+        match self {
+            Encoding::Json => 0b0000_0000,
+            Encoding::MessagePack => 0b0000_0010,
+            Encoding::Cbor => 0b0000_0100,
+        }
+    }
+
+    /// Encoding of a frame payload, None if the flags name an unknown one
+    pub fn from_flags(flags: u8) -> Option<Encoding> {
+        match flags & FLAG_ENCODING_MASK {
+            0b0000_0000 => Some(Encoding::Json),
+            0b0000_0010 => Some(Encoding::MessagePack),
+            0b0000_0100 => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Vec<u8> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).unwrap(),
+            // Named fields, so that structs can be read back whatever the order of their fields
+            Encoding::MessagePack => rmp_serde::to_vec_named(value).unwrap(),
+            Encoding::Cbor => {
+                let mut bytes = vec![];
+                ciborium::into_writer(value, &mut bytes).unwrap();
+                bytes
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(|error| error.to_string()),
+            Encoding::MessagePack => rmp_serde::from_slice(bytes).map_err(|error| error.to_string()),
+            Encoding::Cbor => ciborium::from_reader(bytes).map_err(|error| error.to_string()),
+        }
+    }
+
+    /// Re-encodes a JSON message, the empty messages of untyped topics stay empty
+    pub fn encode_json(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        if self == Encoding::Json || payload.is_empty() {
+            return Ok(payload.to_vec());
+        }
+
+        let value: Value = Encoding::Json.decode(payload)?;
+
+        Ok(self.encode(&value))
+    }
+
+    /// Converts a message to JSON, the empty messages of untyped topics stay empty
+    pub fn to_json(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        if self == Encoding::Json || payload.is_empty() {
+            return Ok(payload.to_vec());
+        }
+
+        let value: Value = self.decode(payload)?;
+
+        Ok(Encoding::Json.encode(&value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::message::Message;
+    use crate::server::protocol::{Frame, ProtocolError, read_request};
+    use crate::server::testing::{KINDS, Rng, valid_request};
+    use super::*;
+
+    #[test]
+    fn binary_requests_are_decoded() {
+        let mut rng = Rng(0x4F1BBCDCBFA53E0B);
+
+        for encoding in [Encoding::MessagePack, Encoding::Cbor] {
+            for _ in 0..500 {
+                let kind = KINDS[rng.below(KINDS.len() - 1)];
+                let request: Message = serde_json::from_value(valid_request(&mut rng, kind)).unwrap();
+
+                let bytes = Frame::encoded_request(&request, encoding).to_bytes();
+                let message = read_request(&mut bytes.as_slice())
+                    .unwrap_or_else(|error| panic!("Valid {:?} {} request rejected: {}", encoding, kind, error))
+                    .unwrap();
+
+                assert_eq!(serde_json::to_value(&message).unwrap(), serde_json::to_value(&request).unwrap());
+            }
+        }
+
+        let mut bytes = Frame::request(&Message::default()).to_bytes();
+        bytes[2] = FLAG_ENCODING_MASK;
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::UnknownEncoding(_))));
+    }
+
+    #[test]
+    fn messages_are_transcoded_both_ways() {
+        let mut rng = Rng(0x94D049BB133111EB);
+
+        for _ in 0..2_000 {
+            let json = serde_json::to_vec(&rng.json(4)).unwrap();
+
+            for encoding in [Encoding::Json, Encoding::MessagePack, Encoding::Cbor] {
+                let encoded = encoding.encode_json(&json).unwrap();
+                let decoded: Value = serde_json::from_slice(&encoding.to_json(&encoded).unwrap()).unwrap();
+
+                assert_eq!(decoded, serde_json::from_slice::<Value>(&json).unwrap());
+            }
+        }
+
+        assert!(Encoding::Cbor.encode_json(&[]).unwrap().is_empty());
+    }
+}
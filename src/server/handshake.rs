@@ -0,0 +1,332 @@
+use std::env;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use crate::message::message::Message;
+use crate::server::compression::Compression;
+use crate::server::encoding::Encoding;
+use crate::server::info::client_address;
+use crate::server::protocol::{ErrorReply, Frame, FrameKind, PROTOCOL_VERSION, ProtocolError};
+use crate::server::transport::Connection;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Environment variable naming the client in its hello, `grf node run` sets it to the name of the node
+pub const CLIENT_NAME_VARIABLE: &str = "GRF_CLIENT_NAME";
+
+/// Features a client or the server can use on top of plain JSON frames
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    /// Encodings the peer reads and writes, JSON is required as acks and errors always use it
+    pub encodings: Vec<Encoding>,
+    pub compressions: Vec<Compression>,
+    /// Whether the peer can read and write messages in shared memory
+    pub shared_memory: bool,
+    /// Whether the peer reads the data frames carrying a message header
+    pub headers: bool,
+}
+
+/// First frame of every connection, tells the server who the client is and what it understands
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub protocol: u8,
+    /// Name of the client, such as the node it belongs to
+    pub client: String,
+    pub capabilities: Capabilities,
+}
+
+/// Client of a connection whose hello was accepted by the server
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// Number of the connection, unique as long as the server runs
+    pub id: u64,
+    pub hello: Hello,
+    /// Address of the client, kept as it cannot be read once the connection is lost
+    pub address: String,
+}
+
+/// Payload of the ack answering a hello
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HelloReply {
+    /// Version spoken on the connection, the one of the server as clients must speak the same
+    pub protocol: u8,
+    /// Name and version of the server
+    pub server: String,
+    /// Features both the client and the server support, the only ones the frames of the connection can use
+    pub capabilities: Capabilities,
+}
+
+impl Capabilities {
+    /// Everything this build supports
+    pub fn all() -> Capabilities {
+        Capabilities {
+            encodings: vec![Encoding::Json, Encoding::MessagePack, Encoding::Cbor],
+            compressions: vec![Compression::None, Compression::Lz4, Compression::Zstd],
+            shared_memory: true,
+            headers: true,
+        }
+    }
+
+    /// Features supported by both peers
+    pub fn common(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            encodings: self.encodings.iter().filter(|encoding| other.encodings.contains(encoding)).copied().collect(),
+            compressions: self.compressions.iter().filter(|compression| other.compressions.contains(compression)).copied().collect(),
+            shared_memory: self.shared_memory && other.shared_memory,
+            headers: self.headers && other.headers,
+        }
+    }
+}
+
+impl Hello {
+    /// Hello of this client, named after `GRF_CLIENT_NAME` when it is set
+    pub fn new() -> Hello {
+        Hello {
+            protocol: PROTOCOL_VERSION,
+            client: env::var(CLIENT_NAME_VARIABLE).unwrap_or(format!("grf {}", env!("CARGO_PKG_VERSION"))),
+            capabilities: Capabilities::all(),
+        }
+    }
+
+    /// Checks the server can talk to the client
+    pub fn check(&self) -> Result<(), ProtocolError> {
+        if self.protocol != PROTOCOL_VERSION {
+            return Err(ProtocolError::IncompatibleClient(format!(
+                "Client \"{}\" speaks protocol version {}, the server only speaks version {}",
+                self.client, self.protocol, PROTOCOL_VERSION,
+            )));
+        }
+
+        if !self.capabilities.encodings.contains(&Encoding::Json) {
+            return Err(ProtocolError::IncompatibleClient(format!("Client \"{}\" does not read JSON, which acks and errors use", self.client)));
+        }
+
+        Ok(())
+    }
+
+    /// Checks a request only asks for what the client announced
+    pub fn check_request(&self, message: &Message) -> Result<(), ProtocolError> {
+        let capabilities = &self.capabilities;
+
+        let unannounced = if message.encoding.is_some_and(|encoding| !capabilities.encodings.contains(&encoding)) {
+            Some(format!("{:?} encoding", message.encoding.unwrap()))
+        }
+        else if message.compression.is_some_and(|compression| !capabilities.compressions.contains(&compression)) {
+            Some(format!("{:?} compression", message.compression.unwrap()))
+        }
+        else if message.shared_memory_length.is_some() && !capabilities.shared_memory {
+            Some("shared memory".to_string())
+        }
+        else if message.header == Some(true) && !capabilities.headers {
+            Some("message headers".to_string())
+        }
+        else {
+            None
+        };
+
+        match unannounced {
+            Some(feature) => Err(ProtocolError::IncompatibleClient(format!("Client \"{}\" asked for {} without announcing it in its hello", self.client, feature))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Session {
+    pub fn new(hello: Hello, stream: &Connection) -> Session {
+        Session {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            hello,
+            address: client_address(stream),
+        }
+    }
+
+    /// Name of the client followed by the number of its connection, such as `talker#12`
+    ///
+    /// Message headers, events and kicks use it, as local socket clients all share the same address
+    pub fn client(&self) -> String {
+        format!("{}#{}", self.hello.client, self.id)
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Hello::new()
+    }
+}
+
+/// Reads the hello starting a connection
+///
+/// Returns `None` if the connection was closed before sending anything
+pub fn read_hello<R: Read>(reader: &mut R) -> Result<Option<Hello>, ProtocolError> {
+    let Some(frame) = Frame::read_from(reader)? else {
+        return Ok(None);
+    };
+
+    match frame.kind {
+        FrameKind::Hello => {
+            let hello: Hello = frame.json().map_err(|error| ProtocolError::MalformedMessage(error.to_string()))?;
+            hello.check()?;
+
+            Ok(Some(hello))
+        }
+        // Clients predating the handshake start with their request
+        _ => Err(ProtocolError::HandshakeRequired),
+    }
+}
+
+/// Introduces the client to the server, the error tells why the server refused it
+///
+/// Refusals are `Unsupported` errors, unlike the connection failures
+pub fn say_hello<S: Read + Write>(stream: &mut S) -> std::io::Result<HelloReply> {
+    Frame::hello(&Hello::new()).write_to(stream)?;
+
+    let response = Frame::read_from(stream)?
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Connection closed by the server"))?;
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json()?;
+
+        // Servers predating the handshake do not know its frame
+        let message = match error.code.as_str() {
+            "unknown_frame_kind" => "The server is older than this client and must be updated".to_string(),
+            _ => error.message,
+        };
+
+        return Err(Error::new(ErrorKind::Unsupported, message));
+    }
+
+    let reply: HelloReply = response.json()?;
+
+    if reply.protocol != PROTOCOL_VERSION {
+        return Err(Error::new(ErrorKind::Unsupported, format!("The server speaks protocol version {}, this client only speaks version {}", reply.protocol, PROTOCOL_VERSION)));
+    }
+
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::server::compression::Compression;
+    use crate::server::protocol::LEGACY_PROTOCOL_VERSION;
+    use crate::server::testing::{frame_bytes, request_bytes};
+    use super::*;
+
+    #[test]
+    fn connections_start_with_a_compatible_hello() {
+        let hello = Hello::new();
+
+        let bytes = Frame::hello(&hello).to_bytes();
+        assert_eq!(read_hello(&mut bytes.as_slice()).unwrap(), Some(hello.clone()));
+        assert!(matches!(read_hello(&mut [].as_slice()), Ok(None)));
+
+        let bytes = request_bytes(&json!({"kind": "list"}));
+        assert_eq!(read_hello(&mut bytes.as_slice()).unwrap_err().code(), "handshake_required");
+
+        // Clients predating the hello send version 1 requests
+        let bytes = frame_bytes(LEGACY_PROTOCOL_VERSION, FrameKind::Request as u8, b"{\"kind\": \"list\"}");
+        assert_eq!(read_hello(&mut bytes.as_slice()).unwrap_err().code(), "handshake_required");
+
+        let incompatible = [
+            Hello { protocol: PROTOCOL_VERSION + 1, ..hello.clone() },
+            Hello { protocol: LEGACY_PROTOCOL_VERSION, ..hello.clone() },
+            Hello { capabilities: Capabilities { encodings: vec![Encoding::Cbor], ..Capabilities::all() }, ..hello.clone() },
+        ];
+
+        for hello in incompatible {
+            let bytes = Frame::hello(&hello).to_bytes();
+            let error = read_hello(&mut bytes.as_slice()).unwrap_err();
+            assert_eq!(error.code(), "incompatible_client", "{:?}", hello);
+        }
+
+        // Newer clients send frames of their own version, the refusal names both
+        let payload = serde_json::to_vec(&Hello { protocol: PROTOCOL_VERSION + 1, ..hello }).unwrap();
+        let bytes = frame_bytes(PROTOCOL_VERSION + 1, FrameKind::Hello as u8, &payload);
+        let error = read_hello(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.code(), "unsupported_version");
+        assert!(error.to_string().contains(&format!("version {} is not supported, only version {}", PROTOCOL_VERSION + 1, PROTOCOL_VERSION)), "{}", error);
+    }
+
+    #[test]
+    fn requests_only_use_announced_capabilities() {
+        let hello = Hello {
+            capabilities: Capabilities {
+                encodings: vec![Encoding::Json],
+                compressions: vec![Compression::None],
+                shared_memory: false,
+                headers: false,
+            },
+            ..Hello::new()
+        };
+
+        let allowed = Message { kind: "sub".to_string(), encoding: Some(Encoding::Json), compression: Some(Compression::None), ..Default::default() };
+        assert!(hello.check_request(&allowed).is_ok());
+        assert!(Hello::new().check_request(&Message { encoding: Some(Encoding::Cbor), header: Some(true), ..allowed.clone() }).is_ok());
+
+        let unannounced = [
+            Message { encoding: Some(Encoding::MessagePack), ..allowed.clone() },
+            Message { compression: Some(Compression::Zstd), ..allowed.clone() },
+            Message { shared_memory_length: Some(1 << 20), ..allowed.clone() },
+            Message { header: Some(true), ..allowed.clone() },
+        ];
+
+        for message in unannounced {
+            assert_eq!(hello.check_request(&message).unwrap_err().code(), "incompatible_client");
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn server_refuses_requests_using_unannounced_capabilities() {
+        use std::os::unix::net::UnixStream;
+        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::AtomicBool;
+        use std::sync::mpsc::channel;
+        use std::thread;
+        use std::time::Instant;
+        use crate::server::pool::ThreadPool;
+        use crate::server::serve::{AtomicTopics, handle_connection, ServerState};
+
+        // A server without topics, it only has to greet and refuse the client
+        let state = ServerState {
+            topics: AtomicTopics { topics: Arc::new(Mutex::new(vec![])), topics_file: Arc::new(std::env::temp_dir().join("grf-test.topics.json")) },
+            services: Default::default(),
+            actions: Default::default(),
+            parameters: Default::default(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            in_flight: Default::default(),
+            stop_requests: channel().0,
+            address: "127.0.0.1:0".to_string(),
+            port: "0".to_string(),
+            started: Instant::now(),
+            persistent: false,
+        };
+
+        let (server_end, mut client) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || handle_connection(Connection::Unix(server_end), state, &ThreadPool::new(1)));
+
+        let hello = Hello {
+            capabilities: Capabilities { encodings: vec![Encoding::Json], compressions: vec![Compression::None], shared_memory: false, headers: true },
+            ..Hello::new()
+        };
+        Frame::hello(&hello).write_to(&mut client).unwrap();
+
+        // Only the features both sides support are granted
+        let reply: HelloReply = Frame::read_from(&mut client).unwrap().unwrap().json().unwrap();
+        assert_eq!(reply.protocol, PROTOCOL_VERSION);
+        assert_eq!(reply.capabilities, hello.capabilities.common(&Capabilities::all()));
+
+        let request = json!({"kind": "sub", "topic": "a", "encoding": "cbor"});
+        client.write_all(&request_bytes(&request)).unwrap();
+
+        let response = Frame::read_from(&mut client).unwrap().unwrap();
+        let error: ErrorReply = response.json().unwrap();
+        assert_eq!(response.kind, FrameKind::Error);
+        assert_eq!(error.code, "incompatible_client");
+        assert!(error.message.contains("Cbor encoding"), "{}", error.message);
+
+        // The connection is closed once refused
+        assert!(matches!(Frame::read_from(&mut client), Ok(None)));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::Outgoing;
+use crate::server::transport::Connection;
+
+/// System topic on which the server publishes its events
+pub const INFO_TOPIC: &str = "info";
+
+/// Something that happened in the server, published on the info topic
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SystemEvent {
+    /// A client sent a request, every request comes on its own connection
+    ClientConnected {
+        /// Name the client gave in its hello followed by the number of its connection
+        client: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<String>,
+        request: String,
+    },
+    /// The connection of a subscriber or a provider was lost
+    ClientDisconnected { client: String, reason: String },
+    TopicCreated { topic: String, message_type: Option<String> },
+    TopicDeleted { topic: String },
+    /// A topic without subscribers was given another message type
+    TopicRetyped { topic: String, message_type: Option<String> },
+    /// The retained message and the subscribers of the topic were dropped
+    TopicCleared { topic: String },
+    SubscriptionAdded { topic: String, subscriber: u64, client: String },
+    SubscriptionRemoved { topic: String, subscriber: u64 },
+    /// A message or a subscription did not match the topic
+    ValidationFailed { topic: String, client: String, errors: Vec<String> },
+    /// A request could not be read or lacked a field
+    RequestRejected { client: String, code: String, message: String },
+}
+
+/// Event as published, with the time it happened at
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InfoEvent {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: SystemEvent,
+}
+
+/// Address of the client at the other end of the stream
+pub fn client_address(stream: &Connection) -> String {
+    stream.peer_addr()
+        .map(|address| address.to_string())
+        .unwrap_or("local socket".to_string())
+}
+
+/// Current time in milliseconds since the Unix epoch
+pub fn timestamp_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Publishes the event to the subscribers of the info topic, the topics must not be locked
+pub fn publish_event(topics: &AtomicTopics, event: SystemEvent) {
+    let info_event = InfoEvent {
+        timestamp: timestamp_millis(),
+        event,
+    };
+
+    topics.write_to_subscribers(INFO_TOPIC, Outgoing::new(serde_json::to_vec(&info_event).unwrap()));
+}
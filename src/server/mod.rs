@@ -1 +1,22 @@
 pub mod serve;
+pub mod pool;
+pub mod protocol;
+pub mod qos;
+pub mod service;
+pub mod topic;
+pub mod action;
+pub mod param;
+pub mod shutdown;
+pub mod daemon;
+pub mod status;
+pub mod info;
+pub mod config;
+pub mod persist;
+pub mod transport;
+pub mod shared_memory;
+pub mod encoding;
+pub mod compression;
+pub mod handshake;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,240 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::Outgoing;
+
+/// System topic on which the parameter changes are published
+pub const PARAMETERS_TOPIC: &str = "parameters";
+
+/// Separator of the hierarchical parameter keys, as in "robot/arm/max_speed"
+pub const KEY_SEPARATOR: char = '/';
+
+/// What happened to a parameter
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Set,
+    Deleted,
+}
+
+/// Notification published on the parameters topic
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ParameterChange {
+    pub key: String,
+    pub change: ChangeKind,
+    /// New value of the parameter, None when it was deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+/// Parameters tree, every key segment is a level of nested JSON objects
+#[derive(Clone)]
+pub struct AtomicParameters {
+    pub(crate) parameters: Arc<Mutex<Value>>,
+}
+
+impl Default for AtomicParameters {
+    fn default() -> Self {
+        AtomicParameters {
+            parameters: Arc::new(Mutex::new(Value::Object(Map::new()))),
+        }
+    }
+}
+
+/// Splits a key in its segments, an empty key or a lone separator stands for the whole tree
+pub fn parse_key(key: &str) -> Result<Vec<&str>, String> {
+    let key = key.trim_matches(KEY_SEPARATOR);
+
+    if key.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let segments: Vec<&str> = key.split(KEY_SEPARATOR).collect();
+
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return Err(format!("Parameter key \"{}\" has an empty segment", key));
+    }
+
+    Ok(segments)
+}
+
+/// Lists the leaves of a parameters tree with their full keys
+pub fn flatten_parameters(prefix: &str, value: &Value) -> Vec<(String, Value)> {
+    match value {
+        Value::Object(children) if !children.is_empty() || prefix.is_empty() => {
+            children.iter()
+                .flat_map(|(name, child)| {
+                    let key = if prefix.is_empty() { name.clone() } else { format!("{}{}{}", prefix, KEY_SEPARATOR, name) };
+                    flatten_parameters(&key, child)
+                })
+                .collect()
+        }
+        _ => vec![(prefix.to_string(), value.clone())],
+    }
+}
+
+/// Publishes a parameter change on the parameters topic
+pub fn publish_change(topics: &AtomicTopics, change: &ParameterChange) {
+    topics.write_to_subscribers(PARAMETERS_TOPIC, Outgoing::new(serde_json::to_vec(change).unwrap()));
+}
+
+impl AtomicParameters {
+    pub fn lock(&self) -> MutexGuard<'_, Value> {
+        self.parameters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Value of a parameter, or the subtree below the key
+    pub fn get(&self, key: &[&str]) -> Option<Value> {
+        let parameters = self.lock();
+
+        key.iter()
+            .try_fold(&*parameters, |value, segment| value.get(segment))
+            .cloned()
+    }
+
+    /// Sets a parameter, the missing levels of the key are created
+    pub fn set(&self, key: &[&str], new_value: Value) -> Result<(), String> {
+        set_in(&mut self.lock(), key, new_value)
+    }
+
+    /// Sets several parameters at once, none of them is set if one cannot be
+    pub fn set_all(&self, values: Vec<(Vec<&str>, Value)>) -> Result<(), Vec<String>> {
+        let mut parameters = self.lock();
+
+        // The parameters are set in a copy of the tree, which replaces it only if they all were
+        let mut updated = parameters.clone();
+        let errors: Vec<String> = values.into_iter()
+            .filter_map(|(key, new_value)| set_in(&mut updated, &key, new_value).err())
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        *parameters = updated;
+
+        Ok(())
+    }
+
+    /// Deletes a parameter or a group of parameters, the groups left empty are deleted too
+    pub fn delete(&self, key: &[&str]) -> Option<Value> {
+        fn delete_in(value: &mut Value, key: &[&str]) -> Option<Value> {
+            let children = value.as_object_mut()?;
+            let (segment, rest) = key.split_first()?;
+
+            if rest.is_empty() {
+                return children.remove(*segment);
+            }
+
+            let child = children.get_mut(*segment)?;
+            let deleted = delete_in(child, rest);
+
+            if child.as_object().is_some_and(|grand_children| grand_children.is_empty()) {
+                children.remove(*segment);
+            }
+
+            deleted
+        }
+
+        delete_in(&mut self.lock(), key)
+    }
+}
+
+/// Sets a parameter in a tree, the missing levels of the key are created
+fn set_in(parameters: &mut Value, key: &[&str], new_value: Value) -> Result<(), String> {
+    let Some((name, parents)) = key.split_last() else {
+        return Err("The whole parameters tree cannot be replaced".to_string());
+    };
+
+    let mut value = parameters;
+
+    for (depth, segment) in parents.iter().enumerate() {
+        let Value::Object(children) = value else {
+            return Err(format!("Parameter \"{}\" is not a group of parameters", parents[..depth].join(&KEY_SEPARATOR.to_string())));
+        };
+
+        value = children.entry(segment.to_string()).or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    let Value::Object(children) = value else {
+        return Err(format!("Parameter \"{}\" is not a group of parameters", parents.join(&KEY_SEPARATOR.to_string())));
+    };
+
+    children.insert(name.to_string(), new_value);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn keys_are_split_in_segments() {
+        assert_eq!(parse_key(""), Ok(vec![]));
+        assert_eq!(parse_key("/"), Ok(vec![]));
+        assert_eq!(parse_key("/robot/arm/speed/"), Ok(vec!["robot", "arm", "speed"]));
+        assert!(parse_key("robot//speed").is_err());
+    }
+
+    #[test]
+    fn parameters_are_set_only_in_groups() {
+        let parameters = AtomicParameters::default();
+
+        parameters.set(&["robot", "arm", "speed"], json!(2.5)).unwrap();
+        assert_eq!(parameters.get(&["robot"]), Some(json!({ "arm": { "speed": 2.5 } })));
+
+        // A parameter with a value cannot hold other parameters
+        assert!(parameters.set(&["robot", "arm", "speed", "max"], json!(3)).is_err());
+        assert!(parameters.set(&["robot", "arm", "speed", "max", "unit"], json!("m/s")).is_err());
+        assert!(parameters.set(&[], json!({})).is_err());
+        assert_eq!(parameters.get(&["robot", "arm", "speed"]), Some(json!(2.5)));
+    }
+
+    #[test]
+    fn parameters_are_set_all_at_once_or_not_at_all() {
+        let parameters = AtomicParameters::default();
+        parameters.set(&["name"], json!("robot")).unwrap();
+
+        let errors = parameters.set_all(vec![(vec!["speed"], json!(1)), (vec!["name", "first"], json!("r2"))]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(*parameters.lock(), json!({ "name": "robot" }));
+
+        parameters.set_all(vec![(vec!["speed"], json!(1)), (vec!["arm", "length"], json!(0.8))]).unwrap();
+        assert_eq!(*parameters.lock(), json!({ "name": "robot", "speed": 1, "arm": { "length": 0.8 } }));
+    }
+
+    #[test]
+    fn deleting_parameters_prunes_empty_groups() {
+        let parameters = AtomicParameters::default();
+        parameters.set(&["robot", "arm", "speed"], json!(2.5)).unwrap();
+        parameters.set(&["robot", "name"], json!("r2")).unwrap();
+
+        assert_eq!(parameters.delete(&["robot", "arm", "speed"]), Some(json!(2.5)));
+        assert_eq!(*parameters.lock(), json!({ "robot": { "name": "r2" } }));
+
+        assert_eq!(parameters.delete(&["robot", "arm"]), None);
+        assert_eq!(parameters.delete(&["robot", "name", "first"]), None);
+
+        assert_eq!(parameters.delete(&["robot", "name"]), Some(json!("r2")));
+        assert_eq!(*parameters.lock(), json!({}));
+    }
+
+    #[test]
+    fn parameters_are_flattened_to_their_leaves() {
+        let tree = json!({ "robot": { "arm": { "speed": 2.5 }, "tools": {} }, "name": "r2" });
+
+        let mut leaves = flatten_parameters("", &tree);
+        leaves.sort_by(|(first, _), (second, _)| first.cmp(second));
+
+        assert_eq!(leaves, vec![
+            ("name".to_string(), json!("r2")),
+            ("robot/arm/speed".to_string(), json!(2.5)),
+            ("robot/tools".to_string(), json!({})),
+        ]);
+        assert_eq!(flatten_parameters("", &json!({})), vec![]);
+        assert_eq!(flatten_parameters("speed", &json!(1)), vec![("speed".to_string(), json!(1))]);
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::get_temp_folder;
+use crate::message::message::is_message_type_registered;
+use crate::server::param::AtomicParameters;
+use crate::server::protocol::MessageHeader;
+use crate::server::qos::QoS;
+use crate::server::serve::{AtomicTopics, ServerState};
+use crate::server::topic::{SYSTEM_TOPICS, Topic};
+
+/// Held while the state file is written
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// How often the state of a persistent server is written when it changed
+const PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// State kept across the restarts of a persistent server
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct PersistedState {
+    pub topics: Vec<PersistedTopic>,
+    pub parameters: Value,
+}
+
+/// Topic as written to the state file, its subscribers are not kept
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PersistedTopic {
+    pub name: String,
+    pub message_type: Option<String>,
+    pub latched: bool,
+    pub validation: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qos: Option<QoS>,
+    /// Whether the topic comes from the configuration, it is not restored once removed from it
+    #[serde(default)]
+    pub declared: bool,
+    /// Last message published on a latched topic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retained: Option<Value>,
+    /// Header of the retained message, the sequence of the topic goes on from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retained_header: Option<MessageHeader>,
+}
+
+/// State file of the server listening on the given port
+pub fn state_file_path(port: &str) -> PathBuf {
+    PathBuf::from(get_temp_folder().unwrap()).join(format!("grf-{}.state.json", port))
+}
+
+impl PersistedState {
+    /// Copy of the topics and parameters, system topics are created by the server itself
+    pub fn capture(topics: &AtomicTopics, parameters: &AtomicParameters) -> PersistedState {
+        let topics = topics.lock()
+            .iter()
+            .filter(|topic| !SYSTEM_TOPICS.contains(&topic.name.as_str()))
+            .map(|topic| PersistedTopic {
+                name: topic.name.clone(),
+                message_type: topic.message_type.clone(),
+                latched: topic.latched,
+                validation: topic.schema.is_some(),
+                qos: topic.qos,
+                declared: topic.declared,
+                retained: topic.retained.as_ref().and_then(|retained| serde_json::from_slice(retained).ok()),
+                retained_header: topic.retained_header.clone(),
+            })
+            .collect();
+
+        PersistedState {
+            topics,
+            parameters: parameters.lock().clone(),
+        }
+    }
+
+    /// Writes the state to a temporary file first, so that a crash never leaves a truncated state file
+    pub fn save(&self, path: &Path) {
+        let temporary_path = path.with_extension("tmp");
+        let content = serde_json::to_vec_pretty(self).unwrap();
+
+        let saved = fs::write(&temporary_path, content).and_then(|_| fs::rename(&temporary_path, path));
+
+        if let Err(error) = saved {
+            println!("Could not save the server state to \"{}\": {}", path.display(), error);
+        }
+    }
+
+    /// Recreates the saved topics and parameters, topics already declared by the configuration only get their retained message
+    pub fn restore(self, topics_list: &mut Vec<Topic>, parameters: &AtomicParameters) {
+        for persisted in self.topics {
+            let retained = persisted.retained.as_ref().map(|retained| serde_json::to_vec(retained).unwrap());
+
+            if let Some(topic) = topics_list.iter_mut().find(|topic| topic.name == persisted.name) {
+                if topic.latched && topic.message_type == persisted.message_type {
+                    topic.retained = retained;
+                    persisted.restore_header(topic);
+                }
+
+                continue;
+            }
+
+            if persisted.declared {
+                continue;
+            }
+
+            match persisted.to_topic() {
+                Ok(mut topic) => {
+                    topic.retained = retained;
+                    persisted.restore_header(&mut topic);
+                    topics_list.push(topic);
+                }
+                Err(error) => println!("Could not restore topic {}: {}", persisted.name, error),
+            }
+        }
+
+        if self.parameters.is_object() {
+            *parameters.lock() = self.parameters;
+        }
+    }
+}
+
+impl PersistedTopic {
+    fn to_topic(&self) -> Result<Topic, String> {
+        let topic = match &self.message_type {
+            Some(message_type) if !is_message_type_registered(message_type.clone()) => {
+                return Err(format!("Message type \"{}\" is no longer registered", message_type));
+            }
+            Some(message_type) if self.validation => Topic::with_validation(&self.name, message_type.clone())?,
+            message_type => Topic::new(&self.name, message_type.clone()),
+        };
+
+        let mut topic = topic.latched(self.latched);
+        topic.qos = self.qos;
+
+        Ok(topic)
+    }
+
+    /// Gives back the header of the retained message, so that the sequence numbers keep growing across restarts
+    fn restore_header(&self, topic: &mut Topic) {
+        if let Some(header) = &self.retained_header {
+            *topic.sequence.lock().unwrap_or_else(PoisonError::into_inner) = header.sequence;
+            topic.retained_header = Some(header.clone());
+        }
+    }
+}
+
+/// Reads the state saved by a previous run, if any
+pub fn load_state(path: &Path) -> Option<PersistedState> {
+    let content = fs::read(path).ok()?;
+
+    match serde_json::from_slice(&content) {
+        Ok(state) => Some(state),
+        Err(error) => {
+            println!("Ignoring the malformed state file \"{}\": {}", path.display(), error);
+            None
+        }
+    }
+}
+
+/// Saves the current state of the server
+pub fn save_state(state: &ServerState) {
+    let _saving = SAVING.lock().unwrap_or_else(PoisonError::into_inner);
+
+    PersistedState::capture(&state.topics, &state.parameters).save(&state_file_path(&state.port));
+}
+
+/// Saves the state of the server whenever it changes, until it starts shutting down
+pub fn start_persistence_thread(state: ServerState) {
+    let path = state_file_path(&state.port);
+
+    thread::spawn(move || {
+        let mut saved = PersistedState::capture(&state.topics, &state.parameters);
+
+        loop {
+            thread::sleep(PERSIST_INTERVAL);
+
+            // The shutdown saves the final state, an older one must not be written after it
+            let _saving = SAVING.lock().unwrap_or_else(PoisonError::into_inner);
+
+            if state.shutting_down.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let current = PersistedState::capture(&state.topics, &state.parameters);
+
+            if current != saved {
+                current.save(&path);
+                saved = current;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use crate::server::qos::{Overflow, Reliability};
+    use crate::server::topic::Outgoing;
+    use super::*;
+
+    fn atomic_topics(topics: Vec<Topic>) -> AtomicTopics {
+        AtomicTopics {
+            topics: Arc::new(Mutex::new(topics)),
+            topics_file: Arc::new(std::env::temp_dir().join("grf-persist-test.topics.json")),
+        }
+    }
+
+    fn topic(name: &str, latched: bool, declared: bool) -> Topic {
+        let mut topic = Topic::new(name, None).latched(latched);
+        topic.declared = declared;
+        topic
+    }
+
+    #[test]
+    fn state_is_restored_after_a_restart() {
+        let qos = QoS { depth: 4, overflow: Overflow::DropNewest, reliability: Reliability::Reliable };
+
+        let mut logs = topic("logs", false, false);
+        logs.qos = Some(qos);
+
+        let topics = atomic_topics(vec![topic("parameters", false, false), topic("pose", true, true), logs, topic("removed", false, true)]);
+        let parameters = AtomicParameters::default();
+        parameters.set(&["robot", "speed"], json!(2.5)).unwrap();
+
+        for x in 1..=3 {
+            topics.write_to_subscribers("pose", Outgoing::new(serde_json::to_vec(&json!({ "x": x })).unwrap()));
+        }
+
+        // The state goes through its file as it does between two runs
+        let saved = serde_json::to_vec(&PersistedState::capture(&topics, &parameters)).unwrap();
+        let state: PersistedState = serde_json::from_slice(&saved).unwrap();
+        assert_eq!(state.topics.iter().map(|topic| topic.name.as_str()).collect::<Vec<&str>>(), vec!["pose", "logs", "removed"]);
+
+        // The configuration of the new run declares pose again, with its own settings, and no longer declares removed
+        let mut pose = topic("pose", true, true);
+        pose.qos = Some(QoS::default());
+
+        let mut topics_list = vec![pose];
+        let restored_parameters = AtomicParameters::default();
+        state.restore(&mut topics_list, &restored_parameters);
+
+        assert_eq!(*restored_parameters.lock(), json!({ "robot": { "speed": 2.5 } }));
+        assert_eq!(topics_list.iter().map(|topic| topic.name.as_str()).collect::<Vec<&str>>(), vec!["pose", "logs"]);
+
+        let pose = &topics_list[0];
+        assert_eq!(pose.qos, Some(QoS::default()));
+        assert_eq!(pose.retained.as_deref().map(|retained| serde_json::from_slice::<Value>(retained).unwrap()), Some(json!({ "x": 3 })));
+        assert_eq!(pose.retained_header.as_ref().map(|header| header.sequence), Some(3));
+
+        let logs = &topics_list[1];
+        assert!(!logs.latched && !logs.declared);
+        assert_eq!(logs.qos, Some(qos));
+
+        // Sequence numbers go on from the retained message
+        let topics = atomic_topics(topics_list);
+        topics.write_to_subscribers("pose", Outgoing::new(serde_json::to_vec(&json!({ "x": 4 })).unwrap()));
+
+        assert_eq!(topics.lock()[0].retained_header.as_ref().map(|header| header.sequence), Some(4));
+    }
+}
@@ -0,0 +1,85 @@
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, mpsc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Fixed size pool of workers handling the server connections
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+    /// Creates a pool with the given amount of workers
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0, "Thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|id| Worker::new(id, Arc::clone(&receiver)))
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// Creates a pool sized after the available parallelism of the machine
+    pub fn with_default_size() -> ThreadPool {
+        let parallelism = thread::available_parallelism()
+            .map(|parallelism| parallelism.get())
+            .unwrap_or(1);
+
+        ThreadPool::new((parallelism * 2).max(4))
+    }
+
+    /// Queues the given job, it will be run by the first available worker
+    pub fn execute<F>(&self, job: F) where F: FnOnce() + Send + 'static {
+        if let Some(sender) = self.sender.as_ref() {
+            sender.send(Box::new(job)).ok();
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                thread.join().ok();
+            }
+        }
+    }
+}
+
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        let thread = thread::Builder::new()
+            .name(format!("grf-worker-{id}"))
+            .spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+
+                match job {
+                    // A failing connection must not take the worker down with it
+                    Ok(job) => {
+                        catch_unwind(AssertUnwindSafe(job)).ok();
+                    }
+                    Err(_) => break,
+                }
+            })
+            .expect("Could not spawn server worker");
+
+        Worker {
+            thread: Some(thread),
+        }
+    }
+}
@@ -0,0 +1,747 @@
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::process::exit;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::message::message::Message;
+use crate::server::compression::{Compression, FLAG_COMPRESSION_MASK};
+use crate::server::encoding::{Encoding, FLAG_ENCODING_MASK};
+use crate::server::handshake::{Hello, say_hello};
+use crate::server::shared_memory::SharedMemoryDescriptor;
+use crate::server::transport::{Connection, open_connection};
+
+/// Version of the wire protocol, sent in every frame header
+///
+/// Version 2 connections start with a hello negotiating the flags of their frames.
+/// Both ends must speak the same version, frames of any other one are rejected
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Version of the clients predating the hello, their frames never carry flags
+pub const LEGACY_PROTOCOL_VERSION: u8 = 1;
+
+/// Frame header size: version (1 byte), kind (1 byte), flags (1 byte), payload length (4 bytes, big endian)
+pub const HEADER_LENGTH: usize = 7;
+
+/// Largest payload accepted, bigger frames are rejected before their payload is read
+pub const MAX_PAYLOAD_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// Flag of data frames whose payload is a JSON `SharedMemoryDescriptor`, the message itself is in shared memory
+/// and its encoding is given by the encoding bits
+pub const FLAG_SHARED_MEMORY: u8 = 0b0000_0001;
+
+/// Flag of data frames whose payload is a `MessageEnvelope`, the message along with its header
+pub const FLAG_HEADER: u8 = 0b0010_0000;
+
+/// Kind of a frame, tells how its payload must be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// Client request, the payload is a JSON `Message`
+    Request = 1,
+    /// Request accepted, the payload depends on the request and may be empty
+    Ack = 2,
+    /// Request rejected, the payload is a JSON `ErrorReply`
+    Error = 3,
+    /// Message published on a topic, the payload is its JSON content, empty for untyped topics
+    Data = 4,
+    /// The server is shutting down and closes the connection, the payload is the JSON string of the reason
+    Close = 5,
+    /// First frame of a connection, the payload is a JSON `Hello`
+    Hello = 6,
+}
+
+impl FrameKind {
+    pub fn from_u8(kind: u8) -> Option<FrameKind> {
+        match kind {
+            1 => Some(FrameKind::Request),
+            2 => Some(FrameKind::Ack),
+            3 => Some(FrameKind::Error),
+            4 => Some(FrameKind::Data),
+            5 => Some(FrameKind::Close),
+            6 => Some(FrameKind::Hello),
+            _ => None
+        }
+    }
+}
+
+/// Single unit of data exchanged between the server and its clients
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// Payload options: `FLAG_SHARED_MEMORY`, `FLAG_HEADER`, the encoding bits (`FLAG_ENCODING_MASK`)
+    /// and the compression bits (`FLAG_COMPRESSION_MASK`)
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Payload of an error frame
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorReply {
+    pub code: String,
+    pub message: String,
+    /// Detailed causes of the error, if any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+    /// Types involved in a type_mismatch error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub type_mismatch: Option<TypeMismatch>,
+}
+
+/// Stamp the server puts on every message sent on a topic
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MessageHeader {
+    /// Position of the message on its topic, starting at 1, a gap means messages were dropped
+    pub sequence: u64,
+    /// Address of the publisher, `server` for the messages the server publishes itself
+    pub publisher: String,
+    /// When the publisher sent the message, in milliseconds since the Unix epoch, if it told
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<u64>,
+    /// When the server received the message, in milliseconds since the Unix epoch
+    pub received: u64,
+}
+
+/// Payload of the data frames flagged with `FLAG_HEADER`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageEnvelope {
+    pub header: MessageHeader,
+    /// Content of the message, None for untyped topics
+    pub message: Option<Value>,
+}
+
+/// A request used another message type than the one of its topic
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TypeMismatch {
+    pub topic: String,
+    /// Message type of the topic, None if it is untyped
+    pub expected: Option<String>,
+    /// Message type given in the request
+    pub requested: Option<String>,
+    /// Amount of subscribers of the topic, its type can only be changed when there are none
+    pub subscribers: usize,
+    /// Whether the type can be replaced, system topics and topics declared in the server configuration keep theirs
+    #[serde(default)]
+    pub retypable: bool,
+}
+
+impl TypeMismatch {
+    /// Error frame carrying the mismatch
+    pub fn to_frame(&self) -> Frame {
+        let reply = ErrorReply {
+            code: "type_mismatch".to_string(),
+            message: self.to_string(),
+            details: vec![],
+            type_mismatch: Some(self.clone()),
+        };
+
+        Frame::new(FrameKind::Error, serde_json::to_vec(&reply).unwrap())
+    }
+}
+
+impl Display for TypeMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Topic \"{}\" uses {}, not {}",
+            self.topic,
+            describe_message_type(self.expected.as_ref()),
+            describe_message_type(self.requested.as_ref())
+        )
+    }
+}
+
+/// Message type as shown to the user, untyped topics have none
+pub fn describe_message_type(message_type: Option<&String>) -> String {
+    match message_type {
+        Some(message_type) => format!("message type \"{}\"", message_type),
+        None => "no message type".to_string(),
+    }
+}
+
+/// Reason why a frame or a request could not be read
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The connection failed, or was closed in the middle of a frame
+    Io(Error),
+    UnsupportedVersion(u8),
+    UnknownFrameKind(u8),
+    /// The flags of the frame name an unknown payload encoding
+    UnknownEncoding(u8),
+    /// The flags of the frame name an unknown payload compression
+    UnknownCompression(u8),
+    /// The compressed payload could not be restored
+    DecompressionFailed(String),
+    PayloadTooLarge(u64),
+    /// Another kind of frame was received where a request was expected
+    UnexpectedFrame(FrameKind),
+    /// The request payload is not a valid JSON `Message`
+    MalformedMessage(String),
+    UnknownKind(String),
+    /// The request lacks a field its kind requires
+    MissingField(&'static str),
+    /// The connection did not start with a hello, the client predates the handshake
+    HandshakeRequired,
+    /// The hello or the request of the client asks for something the server cannot do
+    IncompatibleClient(String),
+}
+
+impl ProtocolError {
+    /// Code of the error frame describing the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProtocolError::Io(_) => "io_error",
+            ProtocolError::UnsupportedVersion(_) => "unsupported_version",
+            ProtocolError::UnknownFrameKind(_) => "unknown_frame_kind",
+            ProtocolError::UnknownEncoding(_) => "unknown_encoding",
+            ProtocolError::UnknownCompression(_) => "unknown_compression",
+            ProtocolError::DecompressionFailed(_) => "decompression_failed",
+            ProtocolError::PayloadTooLarge(_) => "payload_too_large",
+            ProtocolError::UnexpectedFrame(_) => "unexpected_frame",
+            ProtocolError::MalformedMessage(_) => "malformed_message",
+            ProtocolError::UnknownKind(_) => "unknown_kind",
+            ProtocolError::MissingField(_) => "missing_field",
+            ProtocolError::HandshakeRequired => "handshake_required",
+            ProtocolError::IncompatibleClient(_) => "incompatible_client",
+        }
+    }
+
+    /// Error frame telling the client what was wrong with its request
+    pub fn to_frame(&self) -> Frame {
+        Frame::error(self.code(), self.to_string())
+    }
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::Io(error) => write!(f, "{}", error),
+            ProtocolError::UnsupportedVersion(version) => write!(f, "Protocol version {} is not supported, only version {} is", version, PROTOCOL_VERSION),
+            ProtocolError::UnknownFrameKind(kind) => write!(f, "Unknown frame kind {}", kind),
+            ProtocolError::UnknownEncoding(flags) => write!(f, "Unknown payload encoding in frame flags {:#010b}", flags),
+            ProtocolError::UnknownCompression(flags) => write!(f, "Unknown payload compression in frame flags {:#010b}", flags),
+            ProtocolError::DecompressionFailed(error) => write!(f, "Could not decompress the payload: {}", error),
+            ProtocolError::PayloadTooLarge(length) => write!(f, "Payload of {} bytes is over the {} bytes limit", length, MAX_PAYLOAD_LENGTH),
+            ProtocolError::UnexpectedFrame(kind) => write!(f, "Expected a request frame, got {:?}", kind),
+            ProtocolError::MalformedMessage(error) => write!(f, "Malformed message: {}", error),
+            ProtocolError::UnknownKind(kind) => write!(f, "Unknown message kind \"{}\"", kind),
+            ProtocolError::MissingField(field) => write!(f, "Missing field \"{}\"", field),
+            ProtocolError::HandshakeRequired => write!(f, "Connections must start with a hello frame, the client is older than the server and must be updated"),
+            ProtocolError::IncompatibleClient(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<Error> for ProtocolError {
+    fn from(error: Error) -> Self {
+        ProtocolError::Io(error)
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(error: ProtocolError) -> Self {
+        match error {
+            ProtocolError::Io(error) => error,
+            error => Error::new(ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+impl Frame {
+    pub fn new(kind: FrameKind, payload: Vec<u8>) -> Frame {
+        Frame {
+            kind,
+            flags: 0,
+            payload,
+        }
+    }
+
+    /// Request frame carrying the given message
+    pub fn request(message: &Message) -> Frame {
+        Frame::encoded_request(message, Encoding::Json)
+    }
+
+    /// Request frame carrying the given message in the given encoding
+    pub fn encoded_request(message: &Message, encoding: Encoding) -> Frame {
+        Frame::new(FrameKind::Request, encoding.encode(message)).encoded(encoding)
+    }
+
+    /// Marks the payload as being in the given encoding, it must already be encoded
+    pub fn encoded(mut self, encoding: Encoding) -> Frame {
+        self.flags = (self.flags & !FLAG_ENCODING_MASK) | encoding.flag();
+        self
+    }
+
+    /// Encoding of the payload, None if the flags name an unknown one
+    pub fn encoding(&self) -> Option<Encoding> {
+        Encoding::from_flags(self.flags)
+    }
+
+    /// Compresses the payload, unless it is too small to be worth it
+    pub fn compressed(mut self, compression: Compression) -> Frame {
+        let compression = compression.for_payload(&self.payload);
+
+        self.payload = compression.compress(&self.payload);
+        self.flags = (self.flags & !FLAG_COMPRESSION_MASK) | compression.flag();
+        self
+    }
+
+    /// Frame with its payload restored, as it was before being compressed
+    pub fn decompressed(mut self) -> Result<Frame, ProtocolError> {
+        let compression = Compression::from_flags(self.flags).ok_or(ProtocolError::UnknownCompression(self.flags))?;
+
+        if compression != Compression::None {
+            self.payload = compression.decompress(&self.payload).map_err(ProtocolError::DecompressionFailed)?;
+            self.flags &= !FLAG_COMPRESSION_MASK;
+        }
+
+        Ok(self)
+    }
+
+    /// Empty acknowledgement frame
+    pub fn ack() -> Frame {
+        Frame::new(FrameKind::Ack, vec![])
+    }
+
+    /// Acknowledgement frame carrying a response
+    pub fn ack_with<T: Serialize>(response: &T) -> Frame {
+        Frame::new(FrameKind::Ack, serde_json::to_vec(response).unwrap())
+    }
+
+    /// Error frame describing why a request was rejected
+    pub fn error(code: &str, message: String) -> Frame {
+        Frame::error_with_details(code, message, vec![])
+    }
+
+    /// Error frame describing why a request was rejected, along with the detailed causes
+    pub fn error_with_details(code: &str, message: String, details: Vec<String>) -> Frame {
+        let reply = ErrorReply {
+            code: code.to_string(),
+            message,
+            details,
+            type_mismatch: None,
+        };
+
+        Frame::new(FrameKind::Error, serde_json::to_vec(&reply).unwrap())
+    }
+
+    /// Data frame carrying a topic message
+    pub fn data(payload: Vec<u8>) -> Frame {
+        Frame::new(FrameKind::Data, payload)
+    }
+
+    /// Data frame carrying a JSON message along with its header, in the given encoding
+    pub fn data_with_header(header: &MessageHeader, payload: &[u8], encoding: Encoding) -> Result<Frame, String> {
+        let message = if payload.is_empty() { None } else { Some(serde_json::from_slice(payload).map_err(|error| error.to_string())?) };
+
+        let envelope = MessageEnvelope {
+            header: header.clone(),
+            message,
+        };
+
+        Ok(Frame {
+            kind: FrameKind::Data,
+            flags: FLAG_HEADER,
+            payload: encoding.encode(&envelope),
+        }.encoded(encoding))
+    }
+
+    /// Data frame pointing to a message written in shared memory
+    pub fn shared_memory(descriptor: &SharedMemoryDescriptor) -> Frame {
+        Frame {
+            kind: FrameKind::Data,
+            flags: FLAG_SHARED_MEMORY,
+            payload: serde_json::to_vec(descriptor).unwrap(),
+        }
+    }
+
+    /// Descriptor of the message if it is in shared memory
+    pub fn shared_memory_descriptor(&self) -> Option<SharedMemoryDescriptor> {
+        if self.kind != FrameKind::Data || self.flags & FLAG_SHARED_MEMORY == 0 {
+            return None;
+        }
+
+        self.json().ok()
+    }
+
+    pub fn hello(hello: &Hello) -> Frame {
+        Frame::new(FrameKind::Hello, serde_json::to_vec(hello).unwrap())
+    }
+
+    /// Last frame sent on a connection before the server shuts down
+    pub fn close(reason: &str) -> Frame {
+        Frame::new(FrameKind::Close, serde_json::to_vec(reason).unwrap())
+    }
+
+    /// Deserializes the JSON payload of the frame
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.payload)
+    }
+
+    /// Encodes the frame, header included
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let length = self.payload.len() as u32;
+
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.payload.len());
+        bytes.push(PROTOCOL_VERSION);
+        bytes.push(self.kind as u8);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+
+        bytes
+    }
+
+    /// Writes the whole frame to the given writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        writer.flush()
+    }
+
+    /// Reads a single frame, waiting for all of its bytes to arrive
+    ///
+    /// Returns `None` if the connection was closed before a new frame started
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Option<Frame>, ProtocolError> {
+        let mut header = [0u8; HEADER_LENGTH];
+
+        let mut read = 0;
+        while read < HEADER_LENGTH {
+            match reader.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed in the middle of a frame header").into()),
+                Ok(count) => read += count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        match header[0] {
+            PROTOCOL_VERSION => {}
+            LEGACY_PROTOCOL_VERSION => return Err(ProtocolError::HandshakeRequired),
+            version => return Err(ProtocolError::UnsupportedVersion(version)),
+        }
+
+        let kind = FrameKind::from_u8(header[1]).ok_or(ProtocolError::UnknownFrameKind(header[1]))?;
+
+        let flags = header[2];
+        let length = u32::from_be_bytes([header[3], header[4], header[5], header[6]]) as u64;
+
+        if length > MAX_PAYLOAD_LENGTH {
+            return Err(ProtocolError::PayloadTooLarge(length));
+        }
+
+        // Not pre-allocating the announced length, the buffer grows as the bytes arrive
+        let mut payload = vec![];
+        reader.take(length).read_to_end(&mut payload)?;
+
+        if payload.len() as u64 != length {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed in the middle of a frame payload").into());
+        }
+
+        Ok(Some(Frame {
+            kind,
+            flags,
+            payload,
+        }))
+    }
+}
+
+/// Second handle on a connection the server keeps, the client gets an error if it cannot be made
+pub fn clone_connection(stream: &mut Connection) -> Option<Connection> {
+    match stream.try_clone() {
+        Ok(clone) => Some(clone),
+        Err(error) => {
+            println!("Could not keep a connection: {}", error);
+            Frame::error("io_error", format!("Server could not keep the connection: {}", error)).write_to(stream).ok();
+            None
+        }
+    }
+}
+
+/// Reads the request starting a connection and checks it carries the fields its kind requires
+///
+/// Returns `None` if the connection was closed before sending anything
+pub fn read_request<R: Read>(reader: &mut R) -> Result<Option<Message>, ProtocolError> {
+    let Some(frame) = Frame::read_from(reader)? else {
+        return Ok(None);
+    };
+
+    if frame.kind != FrameKind::Request {
+        return Err(ProtocolError::UnexpectedFrame(frame.kind));
+    }
+
+    let frame = frame.decompressed()?;
+
+    let encoding = frame.encoding().ok_or(ProtocolError::UnknownEncoding(frame.flags))?;
+    let message: Message = encoding.decode(&frame.payload).map_err(ProtocolError::MalformedMessage)?;
+
+    check_request(&message)?;
+
+    Ok(Some(message))
+}
+
+/// Checks the kind of a request is known and the fields it requires are there
+pub fn check_request(message: &Message) -> Result<(), ProtocolError> {
+    let required_fields = match message.kind.as_str() {
+        "sub" | "pub" | "topic_delete" | "topic_clear" | "topic_info" => vec![("topic", message.topic.is_some())],
+        "unsub" => vec![("topic", message.topic.is_some()), ("subscriber", message.subscriber.is_some())],
+        "topic_kick" => vec![("topic", message.topic.is_some()), ("client", message.client.is_some())],
+        "srv" | "call" => vec![("service", message.service.is_some())],
+        "action" | "goal" => vec![("action", message.action.is_some())],
+        "cancel" => vec![("action", message.action.is_some()), ("goal", message.goal.is_some())],
+        "list" | "srv_list" | "action_list" | "param_get" | "param_set" | "param_delete" | "param_load" | "stop" | "status" => vec![],
+        kind => return Err(ProtocolError::UnknownKind(kind.to_string())),
+    };
+
+    match required_fields.into_iter().find(|(_, present)| !present) {
+        Some((field, _)) => Err(ProtocolError::MissingField(field)),
+        None => Ok(()),
+    }
+}
+
+/// Sends a request and waits for the server response
+pub fn send_request<S: Read + Write>(stream: &mut S, message: &Message) -> std::io::Result<Frame> {
+    send_encoded_request(stream, message, Encoding::Json, Compression::None)
+}
+
+/// Sends a request in the given encoding and compression and waits for the server response, which is always plain JSON
+pub fn send_encoded_request<S: Read + Write>(stream: &mut S, message: &Message, encoding: Encoding, compression: Compression) -> std::io::Result<Frame> {
+    Frame::encoded_request(message, encoding).compressed(compression).write_to(stream)?;
+
+    Frame::read_from(stream)?
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Connection closed by the server"))
+}
+
+/// Connects to the server at the given address and says hello, exits if it cannot be reached or refuses the client
+pub fn connect(server: &str) -> Connection {
+    match open_session(server) {
+        Ok(stream) => stream,
+        Err(error) => {
+            println!("Could not connect to server at \"{}\": {}", server, error);
+            exit(1);
+        }
+    }
+}
+
+/// Connects to the server at the given address and says hello
+pub fn open_session(server: &str) -> std::io::Result<Connection> {
+    let mut stream = open_connection(server)?;
+    say_hello(&mut stream)?;
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::server::testing::{frame_bytes, KINDS, request_bytes, Rng, valid_request};
+    use super::*;
+
+    const FIELDS: [&str; 11] = ["kind", "topic", "message_type", "message", "subscriber", "service", "action", "goal", "key", "qos", "client"];
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+
+        for _ in 0..20_000 {
+            let length = rng.below(64);
+            let bytes = rng.bytes(length);
+
+            let _ = read_request(&mut bytes.as_slice());
+        }
+    }
+
+    #[test]
+    fn random_payloads_are_parsed_or_rejected() {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+
+        for _ in 0..20_000 {
+            let payload = if rng.coin() {
+                let length = rng.below(128);
+                rng.bytes(length)
+            }
+            else {
+                serde_json::to_vec(&rng.json(4)).unwrap()
+            };
+
+            let bytes = frame_bytes(PROTOCOL_VERSION, FrameKind::Request as u8, &payload);
+
+            match read_request(&mut bytes.as_slice()) {
+                Ok(Some(message)) => assert!(check_request(&message).is_ok()),
+                Ok(None) => panic!("A complete frame was read as a closed connection"),
+                Err(ProtocolError::Io(error)) => panic!("A complete frame failed with an I/O error: {}", error),
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn random_requests_are_parsed_or_rejected() {
+        let mut rng = Rng(0xD1B54A32D192ED03);
+
+        for _ in 0..20_000 {
+            let mut request = serde_json::Map::new();
+
+            for _ in 0..rng.below(FIELDS.len()) {
+                let field = FIELDS[rng.below(FIELDS.len())];
+                let value = if field == "kind" && rng.coin() {
+                    json!(KINDS[rng.below(KINDS.len())])
+                }
+                else {
+                    rng.json(2)
+                };
+
+                request.insert(field.to_string(), value);
+            }
+
+            let bytes = request_bytes(&Value::Object(request));
+
+            match read_request(&mut bytes.as_slice()) {
+                Ok(Some(message)) => assert!(check_request(&message).is_ok()),
+                Ok(None) => panic!("A complete frame was read as a closed connection"),
+                Err(error) => assert!(!matches!(error, ProtocolError::Io(_)), "Unexpected I/O error: {}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn valid_requests_round_trip() {
+        let mut rng = Rng(0xA0761D6478BD642F);
+
+        for _ in 0..2_000 {
+            let kind = KINDS[rng.below(KINDS.len() - 1)];
+            let request = valid_request(&mut rng, kind);
+            let bytes = request_bytes(&request);
+
+            let message = read_request(&mut bytes.as_slice())
+                .unwrap_or_else(|error| panic!("Valid {} request rejected: {}", kind, error))
+                .unwrap();
+
+            let mut expected = request.clone();
+            expected.as_object_mut().unwrap().remove("message_type");
+
+            let mut parsed = serde_json::to_value(&message).unwrap();
+            parsed.as_object_mut().unwrap().remove("message_type");
+
+            assert_eq!(parsed, expected);
+        }
+    }
+
+    #[test]
+    fn mutated_requests_never_panic() {
+        let mut rng = Rng(0xE7037ED1A0B428DB);
+
+        for _ in 0..20_000 {
+            let kind = KINDS[rng.below(KINDS.len())];
+            let mut bytes = request_bytes(&valid_request(&mut rng, kind));
+
+            for _ in 0..1 + rng.below(4) {
+                let index = rng.below(bytes.len());
+                bytes[index] = rng.next() as u8;
+            }
+
+            let _ = read_request(&mut bytes.as_slice());
+        }
+    }
+
+    #[test]
+    fn truncated_requests_are_io_errors() {
+        let mut rng = Rng(0x8EBC6AF09C88C6E3);
+        let bytes = request_bytes(&valid_request(&mut rng, "pub"));
+
+        assert!(matches!(read_request(&mut [].as_slice()), Ok(None)));
+
+        for length in 1..bytes.len() {
+            match read_request(&mut &bytes[..length]) {
+                Err(ProtocolError::Io(error)) => assert_eq!(error.kind(), ErrorKind::UnexpectedEof),
+                result => panic!("Truncated request of {} bytes gave {:?}", length, result.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let payload = serde_json::to_vec(&json!({"kind": "list"})).unwrap();
+
+        let bytes = frame_bytes(PROTOCOL_VERSION + 1, FrameKind::Request as u8, &payload);
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::UnsupportedVersion(_))));
+
+        let bytes = frame_bytes(PROTOCOL_VERSION, 0, &payload);
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::UnknownFrameKind(0))));
+
+        let bytes = frame_bytes(PROTOCOL_VERSION, FrameKind::Ack as u8, &payload);
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::UnexpectedFrame(FrameKind::Ack))));
+    }
+
+    #[test]
+    fn oversized_payloads_are_rejected_before_being_read() {
+        let mut bytes = vec![PROTOCOL_VERSION, FrameKind::Request as u8, 0];
+        bytes.extend_from_slice(&(MAX_PAYLOAD_LENGTH as u32 + 1).to_be_bytes());
+
+        assert!(matches!(read_request(&mut bytes.as_slice()), Err(ProtocolError::PayloadTooLarge(_))));
+    }
+
+    #[test]
+    fn requests_are_checked() {
+        let cases = [
+            (json!({"kind": "sub"}), "missing_field"),
+            (json!({"kind": "unsub", "topic": "a"}), "missing_field"),
+            (json!({"kind": "cancel", "action": "a"}), "missing_field"),
+            (json!({"kind": "call"}), "missing_field"),
+            (json!({"kind": "topic_kick", "topic": "a"}), "missing_field"),
+            (json!({"kind": "nope"}), "unknown_kind"),
+            (json!({"topic": "a"}), "malformed_message"),
+            (json!({"kind": "pub", "topic": 12}), "malformed_message"),
+            (json!(["kind", "list"]), "malformed_message"),
+        ];
+
+        for (request, code) in cases {
+            let bytes = request_bytes(&request);
+
+            match read_request(&mut bytes.as_slice()) {
+                Err(error) => assert_eq!(error.code(), code, "{}", request),
+                Ok(_) => panic!("{} was accepted", request),
+            }
+        }
+    }
+
+    #[test]
+    fn headers_travel_with_their_message() {
+        let mut rng = Rng(0xD6E8FEB86659FD93);
+
+        for sequence in 1..500 {
+            let header = MessageHeader {
+                sequence,
+                publisher: if rng.coin() { "server".to_string() } else { format!("127.0.0.1:{}", rng.below(65_536)) },
+                published: if rng.coin() { Some(rng.next() >> 24) } else { None },
+                received: rng.next() >> 24,
+            };
+            let json = if rng.coin() { serde_json::to_vec(&rng.json(3)).unwrap() } else { vec![] };
+
+            for encoding in [Encoding::Json, Encoding::MessagePack, Encoding::Cbor] {
+                let bytes = Frame::data_with_header(&header, &json, encoding).unwrap().to_bytes();
+                let frame = Frame::read_from(&mut bytes.as_slice()).unwrap().unwrap();
+
+                assert_eq!(frame.flags & FLAG_HEADER, FLAG_HEADER);
+                assert_eq!(frame.encoding(), Some(encoding));
+
+                let envelope: MessageEnvelope = encoding.decode(&frame.payload).unwrap();
+                assert_eq!(envelope.header, header);
+                // A null message reads back as no message at all
+                assert_eq!(envelope.message.unwrap_or(Value::Null), serde_json::from_slice(&json).unwrap_or(Value::Null));
+            }
+        }
+    }
+
+    #[test]
+    fn errors_are_described_in_error_frames() {
+        let frame = ProtocolError::MissingField("topic").to_frame();
+        let reply: ErrorReply = frame.json().unwrap();
+
+        assert_eq!(frame.kind, FrameKind::Error);
+        assert_eq!(reply.code, "missing_field");
+        assert_eq!(reply.message, "Missing field \"topic\"");
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Queue depth used when the subscriber does not ask for one
+pub const DEFAULT_QUEUE_DEPTH: usize = 16;
+
+/// Largest queue depth a subscriber can get
+pub const MAX_QUEUE_DEPTH: usize = 1024;
+
+/// How long a publisher waits for a full reliable queue before the subscriber is considered stuck
+pub const RELIABLE_PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What to do with a message sent to a full best effort queue
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// Drop the oldest queued message to make room for the new one
+    DropOldest,
+    /// Drop the new message
+    DropNewest,
+}
+
+/// Delivery guarantee of a subscription
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reliability {
+    /// Messages are dropped following the overflow policy when the queue is full
+    BestEffort,
+    /// Publishers wait for room in the queue, the subscriber is disconnected if it stays full
+    Reliable,
+}
+
+/// Quality of service of a subscription, missing fields get their default value
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct QoS {
+    pub depth: usize,
+    pub overflow: Overflow,
+    pub reliability: Reliability,
+}
+
+impl Default for QoS {
+    fn default() -> Self {
+        QoS {
+            depth: DEFAULT_QUEUE_DEPTH,
+            overflow: Overflow::DropOldest,
+            reliability: Reliability::BestEffort,
+        }
+    }
+}
+
+impl QoS {
+    /// QoS the server agrees to, the depth is kept within the server limits
+    pub fn negotiate(requested: Option<QoS>) -> QoS {
+        let mut qos = requested.unwrap_or_default();
+        qos.depth = qos.depth.clamp(1, MAX_QUEUE_DEPTH);
+
+        qos
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    frames: VecDeque<Arc<Vec<u8>>>,
+    closed: bool,
+    /// No more frames are accepted, the queued ones are still written
+    finishing: bool,
+}
+
+/// Bounded queue of the frames waiting to be written to a subscriber
+pub struct OutboundQueue {
+    qos: QoS,
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    dropped: AtomicU64,
+}
+
+impl OutboundQueue {
+    pub fn new(qos: QoS) -> OutboundQueue {
+        OutboundQueue {
+            qos,
+            state: Mutex::new(QueueState::default()),
+            changed: Condvar::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits for a full reliable queue to have room, it is closed if it stays full
+    ///
+    /// Publishers wait before taking the sequence of the topic, so that a stuck subscriber does not hold the other publishers
+    pub fn wait_for_room(&self) {
+        if self.qos.reliability != Reliability::Reliable {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        while !state.closed && !state.finishing && state.frames.len() >= self.qos.depth {
+            let (new_state, timeout) = self.changed
+                .wait_timeout(state, RELIABLE_PUSH_TIMEOUT)
+                .unwrap_or_else(PoisonError::into_inner);
+            state = new_state;
+
+            if timeout.timed_out() && state.frames.len() >= self.qos.depth {
+                state.closed = true;
+                self.changed.notify_all();
+            }
+        }
+    }
+
+    /// Queues a frame following the QoS without waiting, returns false if the queue is closed
+    ///
+    /// Reliable queues take the frame even when full, its publisher waited for room beforehand
+    /// and they only outgrow their depth when several publishers race for the last place
+    pub fn push(&self, frame: Arc<Vec<u8>>) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        while !state.closed && !state.finishing && state.frames.len() >= self.qos.depth {
+            match (self.qos.reliability, self.qos.overflow) {
+                (Reliability::Reliable, _) => break,
+                (Reliability::BestEffort, Overflow::DropOldest) => {
+                    state.frames.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                (Reliability::BestEffort, Overflow::DropNewest) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+            }
+        }
+
+        if state.closed || state.finishing {
+            return false;
+        }
+
+        state.frames.push_back(frame);
+        self.changed.notify_all();
+
+        true
+    }
+
+    /// Waits for the next frame to write, returns None once the queue is closed
+    pub fn pop(&self) -> Option<Arc<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            if let Some(frame) = state.frames.pop_front() {
+                self.changed.notify_all();
+                return Some(frame);
+            }
+
+            if state.finishing {
+                return None;
+            }
+
+            state = self.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Queues a last frame whatever the depth, the queue ends once it is written
+    pub fn finish(&self, last_frame: Arc<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if !state.closed && !state.finishing {
+            state.frames.push_back(last_frame);
+            state.finishing = true;
+        }
+
+        self.changed.notify_all();
+    }
+
+    /// Closes the queue, pending frames are discarded and waiting publishers released
+    pub fn close(&self) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).closed = true;
+        self.changed.notify_all();
+    }
+
+    /// Amount of messages dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Instant;
+    use super::*;
+
+    fn frame(byte: u8) -> Arc<Vec<u8>> {
+        Arc::new(vec![byte])
+    }
+
+    fn qos(depth: usize, overflow: Overflow, reliability: Reliability) -> QoS {
+        QoS { depth, overflow, reliability }
+    }
+
+    /// Ends the queue then reads what is left in it
+    fn drain(queue: &OutboundQueue) -> Vec<u8> {
+        queue.finish(frame(u8::MAX));
+
+        std::iter::from_fn(|| queue.pop()).map(|frame| frame[0]).filter(|byte| *byte != u8::MAX).collect()
+    }
+
+    #[test]
+    fn best_effort_queues_drop_following_their_overflow() {
+        let queue = OutboundQueue::new(qos(2, Overflow::DropOldest, Reliability::BestEffort));
+
+        assert!((1..=4).all(|byte| queue.push(frame(byte))));
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(drain(&queue), vec![3, 4]);
+
+        let queue = OutboundQueue::new(qos(2, Overflow::DropNewest, Reliability::BestEffort));
+
+        assert!((1..=4).all(|byte| queue.push(frame(byte))));
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(drain(&queue), vec![1, 2]);
+
+        assert_eq!(QoS::negotiate(Some(qos(0, Overflow::DropOldest, Reliability::BestEffort))).depth, 1);
+        assert_eq!(QoS::negotiate(Some(qos(usize::MAX, Overflow::DropOldest, Reliability::BestEffort))).depth, MAX_QUEUE_DEPTH);
+    }
+
+    #[test]
+    fn reliable_queues_wait_for_room_then_close() {
+        let queue = OutboundQueue::new(qos(1, Overflow::DropOldest, Reliability::Reliable));
+
+        // Full reliable queues keep every frame, the publishers wait beforehand
+        assert!(queue.push(frame(1)));
+        assert!(queue.push(frame(2)));
+        assert_eq!(queue.dropped(), 0);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                assert_eq!(queue.pop().map(|frame| frame[0]), Some(1));
+                assert_eq!(queue.pop().map(|frame| frame[0]), Some(2));
+            });
+
+            let started = Instant::now();
+            queue.wait_for_room();
+            assert!(started.elapsed() < RELIABLE_PUSH_TIMEOUT);
+        });
+
+        assert!(queue.push(frame(3)));
+
+        // Nobody reads the queue anymore, the subscriber is stuck
+        let started = Instant::now();
+        queue.wait_for_room();
+        assert!(started.elapsed() >= RELIABLE_PUSH_TIMEOUT);
+
+        assert!(!queue.push(frame(4)));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn finished_queues_are_flushed_and_closed_ones_discarded() {
+        let queue = OutboundQueue::new(qos(2, Overflow::DropOldest, Reliability::Reliable));
+        queue.push(frame(1));
+        queue.push(frame(2));
+
+        // The last frame goes in whatever the depth, nothing can follow it
+        queue.finish(frame(3));
+        assert!(!queue.push(frame(4)));
+        queue.wait_for_room();
+
+        assert_eq!(std::iter::from_fn(|| queue.pop()).map(|frame| frame[0]).collect::<Vec<u8>>(), vec![1, 2, 3]);
+
+        let queue = OutboundQueue::new(qos(1, Overflow::DropOldest, Reliability::Reliable));
+        queue.push(frame(1));
+
+        // Closing releases the publishers waiting for room right away
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                queue.close();
+            });
+
+            let started = Instant::now();
+            queue.wait_for_room();
+            assert!(started.elapsed() < RELIABLE_PUSH_TIMEOUT);
+        });
+
+        assert!(!queue.push(frame(2)));
+        assert_eq!(queue.pop(), None);
+
+        queue.finish(frame(3));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -1,54 +1,214 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::path::PathBuf;
+use std::fs;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::process::exit;
-use std::sync::{Arc, Mutex};
-use std::{thread};
-use generic_robot_framework::models::topic::Topic;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+use crate::action::advertise::handle_message_kind_action;
+use crate::action::cancel::handle_message_kind_cancel;
+use crate::action::goal::handle_message_kind_goal;
+use crate::action::list::handle_message_kind_action_list;
 use crate::get_temp_folder;
-use crate::message::message::Message;
+use crate::param::delete::handle_message_kind_param_delete;
+use crate::param::get::handle_message_kind_param_get;
+use crate::param::load::handle_message_kind_param_load;
+use crate::param::set::handle_message_kind_param_set;
+use crate::message::message::{Message, TopicInfo};
+use crate::server::action::AtomicActions;
+use crate::server::persist::{load_state, start_persistence_thread, state_file_path};
+use crate::server::param::{AtomicParameters, PARAMETERS_TOPIC};
+use crate::server::pool::ThreadPool;
+use crate::server::handshake::{Capabilities, Hello, HelloReply, read_hello, Session};
+use crate::server::protocol::{Frame, PROTOCOL_VERSION, ProtocolError, read_request};
+use crate::server::service::{AtomicServices, start_call_reaper};
+use crate::server::config::load_server_config;
+use crate::server::daemon::{pid_file_path, spawn_daemon};
+use crate::server::info::{client_address, INFO_TOPIC, publish_event, SystemEvent};
+use crate::server::shutdown::{handle_message_kind_stop, start_shutdown_thread};
+use crate::server::status::handle_message_kind_status;
+use crate::server::topic::{Outgoing, Topic, write_to_subscribers};
+use crate::service::advertise::handle_message_kind_srv;
+use crate::service::call::handle_message_kind_call;
+use crate::service::list::handle_message_kind_srv_list;
+use crate::topic::clear::handle_message_kind_topic_clear;
+use crate::topic::delete::handle_message_kind_topic_delete;
+use crate::topic::kick::handle_message_kind_topic_kick;
+use crate::topic::info::handle_message_kind_topic_info;
 use crate::topic::list::handle_message_kind_list;
 use crate::topic::tpub::handle_message_kind_pub;
-use crate::topic::tsub::handle_message_kind_sub;
+use crate::topic::tsub::{handle_message_kind_sub, handle_message_kind_unsub};
+use crate::server::transport::{Connection, socket_file_path};
 
 #[derive(Clone)]
 pub struct AtomicTopics {
-    pub(crate) topics: Arc<Mutex<Vec<Topic>>>
+    pub(crate) topics: Arc<Mutex<Vec<Topic>>>,
+    /// Topics file of the server, read by the clients that cannot reach it
+    pub(crate) topics_file: Arc<PathBuf>,
 }
 
-pub fn run_server(port: Option<String>) {
+/// State shared by all the server connections
+#[derive(Clone)]
+pub struct ServerState {
+    pub topics: AtomicTopics,
+    pub services: AtomicServices,
+    pub actions: AtomicActions,
+    pub parameters: AtomicParameters,
+    /// Set once the server started shutting down, new requests are refused
+    pub shutting_down: Arc<AtomicBool>,
+    /// Requests handed to the pool and not handled yet, the shutdown waits for them before saving the state
+    pub in_flight: Arc<AtomicUsize>,
+    /// Wakes the shutdown thread up with the reason of the shutdown
+    pub stop_requests: Sender<String>,
+    pub address: String,
+    pub port: String,
+    pub started: Instant,
+    /// Whether the topics and parameters are saved to be restored by the next run
+    pub persistent: bool,
+}
+
+/// How long a new connection may stay silent while sending its hello and its request,
+/// shorter than the timeout of the clients so that they are served before giving up
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Amount of connections sending their hello and their request at the same time, the next ones wait for their turn
+const HANDSHAKE_WORKERS: usize = 64;
+
+/// Port used when none is given to the server
+pub const DEFAULT_PORT: &str = "1312";
+
+/// Interface the server listens on when none is given
+pub const DEFAULT_BIND: &str = "127.0.0.1";
+
+/// Address used by the clients when none is given
+pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:1312";
+
+/// Permissions of the local socket, only the user running the server and its group can connect
+#[cfg(unix)]
+const LOCAL_SOCKET_MODE: u32 = 0o660;
+
+/// Topics file of the server listening on the given port, each server has its own
+pub fn topics_file_path(port: &str) -> PathBuf {
+    PathBuf::from(get_temp_folder().unwrap()).join(format!("grf-{}.topics.json", port))
+}
+
+/// Address of the server listening on the given interface and port, the default ones when they are not given
+pub fn server_address(bind: Option<&str>, port: Option<&str>) -> String {
+    format!("{}:{}", bind.unwrap_or(DEFAULT_BIND), port.unwrap_or(DEFAULT_PORT))
+}
+
+pub fn run_server(bind: Option<String>, port: Option<String>, config: Option<PathBuf>, persistent: bool, daemon: bool) {
+    let address = server_address(bind.as_deref(), port.as_deref());
+    let port = port.unwrap_or(DEFAULT_PORT.to_string());
+
+    if TcpStream::connect(&address).is_ok() {
+        println!("A server is already running on {}", address);
+        exit(1);
+    }
+
+    // The configuration is checked before a daemon is started, so that its errors reach the terminal
+    let declared_topics = config.as_deref().map(load_declared_topics).unwrap_or_default();
+
+    if daemon {
+        // The daemon gets the same options, without the daemon flag
+        let mut args = vec!["serve".to_string(), "--port".to_string(), port.clone()];
+        args.extend(bind.map(|bind| ["--bind".to_string(), bind]).into_iter().flatten());
+        args.extend(config.map(|config| ["--config".to_string(), config.display().to_string()]).into_iter().flatten());
+
+        if persistent {
+            args.push("--persist".to_string());
+        }
+
+        spawn_daemon(&port, &address, &args);
+        return;
+    }
+
     println!("Starting server...");
 
-    let address = "127.0.0.1:".to_string() + port.unwrap_or("1312".to_string()).as_str();
-    let listener = TcpListener::bind(&address).unwrap();
+    let listener = TcpListener::bind(&address).unwrap_or_else(|error| {
+        println!("Could not listen on {}: {}", address, error);
+        exit(1);
+    });
 
-    let topics = AtomicTopics::new(Arc::new(Mutex::new(vec![
-        Topic {
-            name: String::from("finish"),
-            message_type: None,
-            subscribers: vec![]
-        },
-        Topic {
-            name: String::from("info"),
-            message_type: None,
-            subscribers: vec![]
+    let pid_file = pid_file_path(&port);
+    fs::write(&pid_file, process::id().to_string()).unwrap_or_else(|error| {
+        println!("Could not write the pidfile \"{}\": {}", pid_file.display(), error);
+    });
+
+    let mut topics_list = vec![
+        Topic::new("finish", None),
+        Topic::new(INFO_TOPIC, None),
+        Topic::new(PARAMETERS_TOPIC, None),
+    ];
+
+    if !declared_topics.is_empty() {
+        println!("Declared {} topics from the configuration", declared_topics.len());
+    }
+
+    topics_list.extend(declared_topics);
+
+    let parameters = AtomicParameters::default();
+
+    if persistent {
+        let state_file = state_file_path(&port);
+
+        if let Some(saved_state) = load_state(&state_file) {
+            saved_state.restore(&mut topics_list, &parameters);
+            println!("Restored the topics and parameters from \"{}\"", state_file.display());
         }
-    ])));
+    }
+
+    let topics = AtomicTopics::new(Arc::new(Mutex::new(topics_list)), topics_file_path(&port));
 
     topics.topics_to_file();
 
+    let (stop_requests, stop_receiver) = channel();
+
+    let state = ServerState {
+        topics,
+        services: AtomicServices::default(),
+        actions: AtomicActions::default(),
+        parameters,
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        stop_requests,
+        address: address.clone(),
+        port,
+        started: Instant::now(),
+        persistent,
+    };
+
+    start_shutdown_thread(state.clone(), stop_receiver);
+    start_call_reaper(state.services.clone());
+
+    if persistent {
+        start_persistence_thread(state.clone());
+    }
+
+    let signal_state = state.clone();
+
+    // SIGINT and SIGTERM stop the server the same way as the finish topic
+    ctrlc::set_handler(move || signal_state.request_shutdown("signal received"))
+        .expect("Could not set the interruption handler");
+
+    let pool = Arc::new(ThreadPool::with_default_size());
+
+    // Idle connections wait for their first frames on workers of their own, not on the request workers
+    let handshakes = Arc::new(ThreadPool::new(HANDSHAKE_WORKERS));
+
+    #[cfg(unix)]
+    listen_on_local_socket(state.clone(), Arc::clone(&pool), Arc::clone(&handshakes));
 
     println!("Server started on: {}", address);
 
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                let topics_local_state = topics.clone();
-                thread::spawn(move || handle_connection(stream, topics_local_state))
-                    .join()
-                    .ok();
+                let local_state = state.clone();
+                let pool = Arc::clone(&pool);
+                handshakes.execute(move || handle_connection(Connection::Tcp(stream), local_state, &pool));
             }
             Err(_) => {
                 println!("Error");
@@ -58,124 +218,336 @@ pub fn run_server(port: Option<String>) {
     }
 }
 
-pub fn handle_connection(mut stream: TcpStream, topics: AtomicTopics) {
-    let http_request = single_request_to_string_vec(&mut stream);
+/// Reads the hello and the request of a new connection on a handshake worker, then hands the request to the pool
+///
+/// Idle connections must not hold a worker of the pool while it waits for their first frames
+pub fn handle_connection(mut stream: Connection, state: ServerState, pool: &ThreadPool) {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok();
 
-    println!("--------");
-    print!("Received: ");
-    for data in http_request.to_vec() {
-        print!("{}, ", data);
-    }
-    println!();
+    let mut session = match read_hello(&mut stream) {
+        Ok(Some(hello)) => Session::new(hello, &stream),
+        Ok(None) => return,
+        Err(error) => return reject_connection(stream, error, &state),
+    };
+
+    let reply = state.hello_reply(&session.hello);
+    Frame::ack_with(&reply).write_to(&mut stream).ok();
+
+    // The requests can only use the negotiated features
+    session.hello.capabilities = reply.capabilities;
 
-    if http_request.len() != 3 {
-        panic!("Malformed request")
+    let message = match read_request(&mut stream) {
+        Ok(Some(message)) => match session.hello.check_request(&message) {
+            Ok(()) => message,
+            Err(error) => return reject_connection(stream, error, &state),
+        },
+        Ok(None) => return,
+        Err(error) => return reject_connection(stream, error, &state),
+    };
+
+    // Counted before the flag is read, so that the request is either refused or waited for by the shutdown
+    let in_flight = InFlightRequest::start(&state.in_flight);
+
+    if state.shutting_down.load(Ordering::SeqCst) {
+        Frame::error("shutting_down", "Server is shutting down".to_string()).write_to(&mut stream).ok();
+        return;
     }
 
-    if http_request[0] != OK_HTTP_STATUS {
-        panic!("Malformed status line")
+    // Subscribers and providers keep their connection open as long as they like once the request is read
+    stream.set_read_timeout(None).ok();
+
+    pool.execute(move || {
+        handle_request(stream, session, message, state);
+        drop(in_flight);
+    });
+}
+
+/// Request counted in the requests in flight until it is dropped, even if its handler panics
+struct InFlightRequest(Arc<AtomicUsize>);
+
+impl InFlightRequest {
+    fn start(in_flight: &Arc<AtomicUsize>) -> InFlightRequest {
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightRequest(Arc::clone(in_flight))
     }
+}
 
-    if !http_request[2].starts_with("Content: ") {
-        panic!("Malformed content")
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
+}
+
+/// Handles a request read from a connection
+fn handle_request(mut stream: Connection, session: Session, message: Message, state: ServerState) {
+    println!("--------");
+    println!("Received from {} ({}): {}", session.client(), session.address, serde_json::to_string(&message).unwrap_or_default());
 
-    let content = &http_request[2]["Content: ".len()..];
-    let message: Message = serde_json::from_str(content).expect("Malformed message");
+    publish_event(&state.topics, SystemEvent::ClientConnected {
+        client: session.client(),
+        address: Some(session.address.clone()),
+        request: message.kind.clone(),
+    });
 
+    // The fields required by the kind were checked when reading the request
     match message.kind.as_str() {
         "sub" => {
-            handle_message_kind_sub(stream, message, topics)
+            handle_message_kind_sub(stream, message, &session, state.topics)
+        }
+        "unsub" => {
+            handle_message_kind_unsub(stream, message, state.topics)
         }
         "pub" => {
-            handle_message_kind_pub(stream, message, topics)
+            let topic_name = message.topic.clone().unwrap();
+
+            if handle_message_kind_pub(stream, message, &session, state.topics.clone()) {
+                handle_generic_topics(topic_name, &state)
+            }
         }
         "list" => {
-            handle_message_kind_list(stream, topics)
+            handle_message_kind_list(stream, state.topics)
         }
-        _ => {
-            panic!("Unknown message kind")
+        "topic_delete" => {
+            handle_message_kind_topic_delete(stream, message, state.topics)
+        }
+        "topic_clear" => {
+            handle_message_kind_topic_clear(stream, message, state.topics)
+        }
+        "topic_kick" => {
+            handle_message_kind_topic_kick(stream, message, state.topics)
+        }
+        "topic_info" => {
+            handle_message_kind_topic_info(stream, message, state.topics)
+        }
+        "srv" => {
+            handle_message_kind_srv(stream, message, &session, state.services, state.topics)
+        }
+        "call" => {
+            handle_message_kind_call(stream, message, state.services)
+        }
+        "srv_list" => {
+            handle_message_kind_srv_list(stream, state.services)
+        }
+        "action" => {
+            handle_message_kind_action(stream, message, &session, state.actions, state.topics)
+        }
+        "goal" => {
+            handle_message_kind_goal(stream, message, state.actions)
+        }
+        "cancel" => {
+            handle_message_kind_cancel(stream, message, state.actions)
+        }
+        "action_list" => {
+            handle_message_kind_action_list(stream, state.actions)
+        }
+        "param_get" => {
+            handle_message_kind_param_get(stream, message, state.parameters)
+        }
+        "param_set" => {
+            handle_message_kind_param_set(stream, message, state.parameters, state.topics)
+        }
+        "param_delete" => {
+            handle_message_kind_param_delete(stream, message, state.parameters, state.topics)
+        }
+        "param_load" => {
+            handle_message_kind_param_load(stream, message, state.parameters, state.topics)
+        }
+        "stop" => {
+            handle_message_kind_stop(stream, state)
+        }
+        "status" => {
+            handle_message_kind_status(stream, state)
+        }
+        kind => {
+            ProtocolError::UnknownKind(kind.to_string()).to_frame().write_to(&mut stream).ok();
         }
     }
 }
 
-/// Handle topics that are generic
-pub fn handle_generic_topics(topic_name: String) {
-    if topic_name.as_str() == "finish" {
-        println!("Closing");
-        exit(0)
+/// Answers a hello or a request the server cannot read with an error, then closes the connection
+fn reject_connection(mut stream: Connection, error: ProtocolError, state: &ServerState) {
+    let client = client_address(&stream);
+    println!("Rejected request from {}: {}", client, error);
+
+    // Nothing can be sent back on a broken connection
+    if !matches!(error, ProtocolError::Io(_)) {
+        error.to_frame().write_to(&mut stream).ok();
+
+        publish_event(&state.topics, SystemEvent::RequestRejected {
+            client,
+            code: error.code().to_string(),
+            message: error.to_string(),
+        });
     }
-}
 
-pub const OK_HTTP_STATUS: &str = "HTTP/1.1 200 OK";
-pub const BAD_REQUEST_HTTP_STATUS: &str = "HTTP/1.1 400 BAD_REQUEST";
-
-/// Returns a single request and returns it a Vector of Strings
-pub fn single_request_to_string_vec(stream: &mut TcpStream) -> Vec<String> {
-    let buf_reader = BufReader::new(stream);
-    buf_reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect()
+    stream.shutdown(Shutdown::Both).ok();
 }
 
-/// Returns a single request and returns it a String
-pub fn single_request_to_string(stream: &mut TcpStream) -> String {
-    let mut buf = vec![0u8; 1024];
-    let mut handle = stream.try_clone().unwrap().take(1024);
-    handle.read(&mut buf).unwrap();
+/// Accepts the local clients on the socket of the server, its file permissions tell who can connect
+#[cfg(unix)]
+fn listen_on_local_socket(state: ServerState, pool: Arc<ThreadPool>, handshakes: Arc<ThreadPool>) {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
 
-    String::from_utf8_lossy(&buf)
-        .chars()
-        .take_while(|&ch| ch != '\0')
-        .collect::<String>()
-}
+    let socket_file = socket_file_path(&state.port);
 
-/// Acknowledgement HTTP request
-pub fn acknowledgement_http_request() -> String {
-    OK_HTTP_STATUS.to_string()
+    // No server answered on the port, the socket left by a previous one is stale
+    fs::remove_file(&socket_file).ok();
+
+    let listener = match UnixListener::bind(&socket_file) {
+        Ok(listener) => listener,
+        Err(error) => {
+            println!("Could not listen on \"{}\", local clients will use TCP: {}", socket_file.display(), error);
+            return;
+        }
+    };
+
+    fs::set_permissions(&socket_file, fs::Permissions::from_mode(LOCAL_SOCKET_MODE)).ok();
+    println!("Local clients accepted on: {}", socket_file.display());
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let local_state = state.clone();
+            let pool = Arc::clone(&pool);
+            handshakes.execute(move || handle_connection(Connection::Unix(stream), local_state, &pool));
+        }
+    });
 }
 
-/// Formatting a String to a HTTP request
-#[allow(dead_code)]
-pub fn string_to_http_request(data: String) -> String {
-    let length = data.len();
+/// Topics of the configuration file, exits if it cannot be used
+fn load_declared_topics(path: &Path) -> Vec<Topic> {
+    let config = load_server_config(path).unwrap_or_else(|error| {
+        println!("{}", error);
+        exit(1);
+    });
 
-    format!("{OK_HTTP_STATUS}\r\nContent-Length: {length}\r\nContent: {data}")
+    config.declared_topics().unwrap_or_else(|errors| {
+        println!("Invalid configuration \"{}\":", path.display());
+
+        for error in errors {
+            println!("  - {}", error);
+        }
+
+        exit(1);
+    })
+}
+
+/// Handle topics that are generic
+pub fn handle_generic_topics(topic_name: String, state: &ServerState) {
+    if topic_name.as_str() == "finish" {
+        state.request_shutdown("finish topic");
+    }
 }
 
-/// Formatting a &Message to a HTTP request
-pub fn message_to_http_request(data: &Message) -> String {
-    let contents = serde_json::to_string(data).unwrap();
-    let length = contents.len();
+impl ServerState {
+    /// Ack of a hello, tells the client the version and the features negotiated for its connection
+    pub fn hello_reply(&self, hello: &Hello) -> HelloReply {
+        HelloReply {
+            protocol: PROTOCOL_VERSION,
+            server: format!("grf {}", env!("CARGO_PKG_VERSION")),
+            capabilities: Capabilities::all().common(&hello.capabilities),
+        }
+    }
 
-    format!("{OK_HTTP_STATUS}\r\nContent-Length: {length}\r\nContent: {contents}")
+    /// Asks the shutdown thread to stop the server
+    pub fn request_shutdown(&self, reason: &str) {
+        self.stop_requests.send(reason.to_string()).ok();
+    }
 }
 
 impl AtomicTopics {
-    const fn new(topics: Arc<Mutex<Vec<Topic>>>) -> AtomicTopics {
+    fn new(topics: Arc<Mutex<Vec<Topic>>>, topics_file: PathBuf) -> AtomicTopics {
         AtomicTopics {
-            topics
+            topics,
+            topics_file: Arc::new(topics_file),
+        }
+    }
+
+    /// Locks the topics list, the lock should be released before doing any network I/O
+    pub fn lock(&self) -> MutexGuard<'_, Vec<Topic>> {
+        self.topics.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stamps the message with the next sequence number of the given topic, keeps it if the topic
+    /// is latched and queues it for the subscribers, dropping those that are gone
+    ///
+    /// The topics are only locked while copying the subscribers list, so slow subscribers
+    /// do not block the other connections, only the publishers of the same topic wait for them
+    pub fn write_to_subscribers(&self, topic_name: &str, mut message: Outgoing) {
+        let Some((sequence, subscribers)) = self.lock()
+            .iter()
+            .find(|topic| topic.name == topic_name)
+            .map(|topic| (Arc::clone(&topic.sequence), topic.subscribers.clone())) else {
+            return;
+        };
+
+        // Reliable subscribers are waited for before taking the sequence, so that it is not held while one of them is stuck
+        for subscriber in &subscribers {
+            subscriber.queue.wait_for_room();
+        }
+
+        // Never taken while the topics are locked, the topics can be locked while holding it
+        let mut sequence = sequence.lock().unwrap_or_else(PoisonError::into_inner);
+        *sequence += 1;
+        let header = message.stamp(*sequence);
+
+        let Some((subscribers, traffic)) = self.lock()
+            .iter_mut()
+            .find(|topic| topic.name == topic_name)
+            .map(|topic| {
+                if topic.latched {
+                    topic.retained = Some(message.payload().to_vec());
+                    topic.retained_header = Some(header);
+                }
+
+                (topic.subscribers.clone(), Arc::clone(&topic.traffic))
+            }) else {
+            return;
+        };
+
+        let dead_subscribers = write_to_subscribers(&subscribers, &mut message, &traffic);
+        drop(sequence);
+
+        if dead_subscribers.is_empty() {
+            return;
+        }
+
+        let mut removed = vec![];
+
+        for topic in self.lock().iter_mut().filter(|topic| topic.name == topic_name) {
+            for id in &dead_subscribers {
+                if let Some(subscriber) = topic.remove_subscriber(*id) {
+                    subscriber.close();
+                    println!("Dropped closed subscriber {} from topic {}", id, topic_name);
+                    removed.push(subscriber.id);
+                }
+            }
+        }
+
+        for subscriber in removed {
+            publish_event(self, SystemEvent::SubscriptionRemoved {
+                topic: topic_name.to_string(),
+                subscriber,
+            });
         }
     }
 
     /// Writes the name of the available topics to the topics file
     pub fn topics_to_file(&self) {
-        let topics_file_path = PathBuf::from(get_temp_folder().unwrap()).join("topics.json");
-
-        let mut topics_file = File::create(topics_file_path).expect("Cannot create topics file");
+        let topics_file_path = &*self.topics_file;
 
-        let topics: HashMap<String, Option<String>> = self.topics
-            .lock()
-            .unwrap()
+        let topics: Vec<TopicInfo> = self.lock()
             .iter()
-            .map(|topic| (topic.name.clone(), topic.message_type.clone()))
+            .map(|topic| topic.info())
             .collect();
 
         let json_topics = serde_json::to_string(&topics).unwrap();
 
-        topics_file.write_all(json_topics.as_bytes()).ok();
+        // Only the completions and the client checks read it, the server keeps going without it
+        if let Err(error) = fs::write(topics_file_path, json_topics) {
+            println!("Could not write the topics file \"{}\": {}", topics_file_path.display(), error);
+        }
     }
 }
 
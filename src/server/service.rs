@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::net::Shutdown;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::message::message::load_schema;
+use crate::server::protocol::Frame;
+use crate::server::transport::Connection;
+
+static NEXT_CALL_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Time a provider has to respond when the caller does not give its own timeout
+pub const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest time a caller can wait for a response, larger timeouts are cut down to it
+pub const MAX_CALL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often the pending calls are checked for a passed deadline
+const CALL_REAP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Description of a service, as sent to the clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceInfo {
+    pub name: String,
+    pub request_type: Option<String>,
+    pub response_type: Option<String>,
+}
+
+/// Call forwarded by the server to a service provider
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceCall {
+    /// Identifier to give back with the response
+    pub call: u64,
+    pub request: Option<Value>,
+}
+
+/// Service advertised by a node, the calls are forwarded through its connection
+pub struct Service {
+    pub info: ServiceInfo,
+    pub request_schema: Option<Arc<JSONSchema>>,
+    pub response_schema: Option<Arc<JSONSchema>>,
+    pub provider: Arc<Mutex<Connection>>,
+}
+
+/// Call waiting for the response of its provider
+pub struct PendingCall {
+    pub service: String,
+    pub caller: Connection,
+    /// When the caller stops waiting and gets an error instead
+    pub deadline: Instant,
+}
+
+#[derive(Clone, Default)]
+pub struct AtomicServices {
+    pub(crate) services: Arc<Mutex<Vec<Service>>>,
+    pub(crate) pending: Arc<Mutex<HashMap<u64, PendingCall>>>,
+}
+
+impl Service {
+    /// Creates a service validating its requests and responses against the schemas of their message types
+    pub fn new(info: ServiceInfo, provider: Connection) -> Result<Service, String> {
+        Ok(Service {
+            request_schema: load_optional_schema(info.request_type.as_ref())?,
+            response_schema: load_optional_schema(info.response_type.as_ref())?,
+            info,
+            provider: Arc::new(Mutex::new(provider)),
+        })
+    }
+}
+
+/// Loads the schema of a message type if there is one
+pub fn load_optional_schema(message_type: Option<&String>) -> Result<Option<Arc<JSONSchema>>, String> {
+    match message_type {
+        Some(message_type) => Ok(Some(Arc::new(load_schema(message_type)?))),
+        None => Ok(None),
+    }
+}
+
+impl AtomicServices {
+    /// Locks the services list, the lock should be released before doing any network I/O
+    pub fn lock(&self) -> MutexGuard<'_, Vec<Service>> {
+        self.services.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Keeps the caller connection until the provider responds or the timeout elapses, returns the call identifier
+    pub fn add_pending_call(&self, service: &str, caller: Connection, timeout: Duration) -> u64 {
+        let call = NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed);
+
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner).insert(call, PendingCall {
+            service: service.to_string(),
+            caller,
+            deadline: Instant::now() + timeout.min(MAX_CALL_TIMEOUT),
+        });
+
+        call
+    }
+
+    /// Removes a pending call, returns it if it was still waiting
+    pub fn take_pending_call(&self, call: u64) -> Option<PendingCall> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner).remove(&call)
+    }
+
+    /// Tells the providers and the waiting callers that the server is shutting down
+    pub fn close_all(&self, reason: &str) {
+        let providers: Vec<Arc<Mutex<Connection>>> = self.lock()
+            .iter()
+            .map(|service| Arc::clone(&service.provider))
+            .collect();
+
+        for provider in providers {
+            let mut provider = provider.lock().unwrap_or_else(PoisonError::into_inner);
+            Frame::close(reason).write_to(&mut *provider).ok();
+            provider.shutdown(Shutdown::Both).ok();
+        }
+
+        let pending_calls: Vec<PendingCall> = self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+            .drain()
+            .map(|(_, pending_call)| pending_call)
+            .collect();
+
+        for mut pending_call in pending_calls {
+            let error = format!("Server is shutting down: {}", reason);
+            Frame::error("server_shutdown", error).write_to(&mut pending_call.caller).ok();
+            pending_call.caller.shutdown(Shutdown::Both).ok();
+        }
+    }
+
+    /// Unregisters a service whose provider left, its pending calls are answered with an error
+    pub fn remove_service(&self, name: &str) {
+        self.lock().retain(|service| service.info.name != name);
+
+        let pending_calls: Vec<PendingCall> = {
+            let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            let calls: Vec<u64> = pending.iter()
+                .filter(|(_, pending_call)| pending_call.service == name)
+                .map(|(call, _)| *call)
+                .collect();
+
+            calls.iter().filter_map(|call| pending.remove(call)).collect()
+        };
+
+        for mut pending_call in pending_calls {
+            let error = format!("Service \"{}\" is no longer available", name);
+            Frame::error("service_unavailable", error).write_to(&mut pending_call.caller).ok();
+            pending_call.caller.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
+
+/// Answers the calls whose provider did not respond before their deadline with an error
+pub fn start_call_reaper(services: AtomicServices) {
+    thread::spawn(move || loop {
+        thread::sleep(CALL_REAP_INTERVAL);
+
+        let now = Instant::now();
+        let expired: Vec<(u64, PendingCall)> = {
+            let mut pending = services.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            let calls: Vec<u64> = pending.iter()
+                .filter(|(_, pending_call)| pending_call.deadline <= now)
+                .map(|(call, _)| *call)
+                .collect();
+
+            calls.into_iter().filter_map(|call| pending.remove_entry(&call)).collect()
+        };
+
+        for (call, mut pending_call) in expired {
+            println!("Call {} to service {} timed out", call, pending_call.service);
+            let error = format!("Service \"{}\" did not respond in time", pending_call.service);
+            Frame::error("call_timeout", error).write_to(&mut pending_call.caller).ok();
+            pending_call.caller.shutdown(Shutdown::Both).ok();
+        }
+    });
+}
@@ -0,0 +1,422 @@
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::sync::{Mutex, PoisonError};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::Duration;
+use memmap2::{MmapOptions, MmapRaw};
+use serde::{Deserialize, Serialize};
+use crate::get_temp_folder;
+use crate::server::protocol::MessageHeader;
+
+/// Messages at least this large go through shared memory when the publisher is on the server host
+pub const SHARED_MEMORY_THRESHOLD: usize = 64 * 1024;
+
+/// Amount of messages a segment holds, a slot is reused once the next ones are written
+pub const SEGMENT_SLOTS: u64 = 8;
+
+/// Largest message a slot holds, larger ones are sent over the socket
+///
+/// A segment maps 32 MiB per topic, only the pages the publishers write take memory
+pub const SLOT_CAPACITY: u64 = 4 * 1024 * 1024;
+
+/// How long the server waits for a publisher to commit the message written in the slot it was given
+pub const COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Segments can be read and written by the user running the server and its group, like its local socket
+#[cfg(unix)]
+const SEGMENT_MODE: u32 = 0o660;
+
+/// First bytes of a segment file
+const SEGMENT_MAGIC: &[u8; 8] = b"GRFSHM01";
+
+/// Segment header: magic, slot count and slot capacity, padded to keep the slots aligned
+const SEGMENT_HEADER_LENGTH: u64 = 64;
+
+/// Slot header: sequence of the message it holds (0 while being written) and message length
+const SLOT_HEADER_LENGTH: u64 = 16;
+
+/// Where a message written in shared memory is, sent over the socket instead of the message itself
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SharedMemoryDescriptor {
+    /// Segment file of the topic
+    pub path: PathBuf,
+    pub slot: u64,
+    /// Sequence of the message, the slot is overwritten once it holds another one
+    pub sequence: u64,
+    pub length: u64,
+    /// Header of the message, stamped by the server when it forwards the descriptor to the subscribers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<MessageHeader>,
+}
+
+/// Ring buffer of messages mapped from a file, each slot is guarded by its sequence number
+///
+/// The server creates the segment of a topic and hands its slots out to the publishers,
+/// which write the message then store its sequence. Readers check the sequence before and
+/// after copying the message, so that a slot overwritten meanwhile is detected
+pub struct Segment {
+    path: PathBuf,
+    map: MmapRaw,
+    slots: u64,
+    capacity: u64,
+    next_sequence: AtomicU64,
+    /// Slots handed out to publishers that did not commit their message yet, they are not handed out again meanwhile
+    pending: Mutex<Vec<u64>>,
+    /// Only the server removes the file once the segment is dropped
+    owned: bool,
+}
+
+/// Folder the segments are created in, memory backed when the system has one
+fn segment_folder() -> PathBuf {
+    let shm = Path::new("/dev/shm");
+
+    if shm.is_dir() {
+        shm.to_path_buf()
+    }
+    else {
+        PathBuf::from(get_temp_folder().unwrap())
+    }
+}
+
+impl Segment {
+    /// Creates the segment of a topic, file names only need to be unique within the server process
+    pub fn create(id: u64) -> Result<Segment> {
+        let path = segment_folder().join(format!("grf-{}-{}.shm", process::id(), id));
+
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(SEGMENT_MODE))?;
+        let length = SEGMENT_HEADER_LENGTH + SEGMENT_SLOTS * (SLOT_HEADER_LENGTH + SLOT_CAPACITY);
+
+        // The file is sparse, only the pages written by the publishers take memory
+        file.set_len(length)?;
+
+        let map = MmapOptions::new().map_raw(&file)?;
+
+        let header = [
+            &SEGMENT_MAGIC[..],
+            &SEGMENT_SLOTS.to_le_bytes(),
+            &SLOT_CAPACITY.to_le_bytes(),
+        ].concat();
+
+        unsafe {
+            ptr::copy_nonoverlapping(header.as_ptr(), map.as_mut_ptr(), header.len());
+        }
+
+        Ok(Segment {
+            path,
+            map,
+            slots: SEGMENT_SLOTS,
+            capacity: SLOT_CAPACITY,
+            next_sequence: AtomicU64::new(1),
+            pending: Mutex::new(vec![]),
+            owned: true,
+        })
+    }
+
+    /// Maps the segment a descriptor points to
+    pub fn open(path: &Path) -> Result<Segment> {
+        let file = File::options().read(true).write(true).open(path)?;
+        let map = MmapOptions::new().map_raw(&file)?;
+
+        if (map.len() as u64) < SEGMENT_HEADER_LENGTH {
+            return Err(Error::new(ErrorKind::InvalidData, "Segment is too small"));
+        }
+
+        let mut header = [0u8; 24];
+
+        unsafe {
+            ptr::copy_nonoverlapping(map.as_ptr(), header.as_mut_ptr(), header.len());
+        }
+
+        let slots = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let capacity = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+        let expected_length = slots.checked_mul(SLOT_HEADER_LENGTH + capacity)
+            .and_then(|length| length.checked_add(SEGMENT_HEADER_LENGTH));
+
+        if &header[..8] != SEGMENT_MAGIC || capacity % 8 != 0 || expected_length != Some(map.len() as u64) {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a GRF shared memory segment"));
+        }
+
+        Ok(Segment {
+            path: path.to_path_buf(),
+            map,
+            slots,
+            capacity,
+            next_sequence: AtomicU64::new(0),
+            pending: Mutex::new(vec![]),
+            owned: false,
+        })
+    }
+
+    /// Hands the next free slot out to a publisher, it must be released once the message is committed or given up
+    ///
+    /// None if the message does not fit in a slot or every slot waits for a commit
+    pub fn claim(&self, length: u64) -> Option<SharedMemoryDescriptor> {
+        if length > self.capacity {
+            return None;
+        }
+
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if pending.len() as u64 >= self.slots {
+            return None;
+        }
+
+        let sequence = loop {
+            let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+
+            if !pending.contains(&(sequence % self.slots)) {
+                break sequence;
+            }
+        };
+
+        pending.push(sequence % self.slots);
+
+        Some(SharedMemoryDescriptor {
+            path: self.path.clone(),
+            slot: sequence % self.slots,
+            sequence,
+            length,
+            header: None,
+        })
+    }
+
+    /// Gives a claimed slot back, a message that was not committed is invalidated so that it is never read
+    pub fn release(&self, descriptor: &SharedMemoryDescriptor, committed: bool) {
+        if descriptor.slot >= self.slots {
+            return;
+        }
+
+        if !committed {
+            let (sequence, _, _) = self.slot(descriptor.slot);
+            sequence.store(0, Ordering::Release);
+        }
+
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner).retain(|slot| *slot != descriptor.slot);
+    }
+
+    /// Writes a message in the slot of the descriptor
+    pub fn write(&self, descriptor: &SharedMemoryDescriptor, message: &[u8]) -> Result<()> {
+        if descriptor.slot >= self.slots || message.len() as u64 > self.capacity || descriptor.sequence == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Descriptor does not match the segment"));
+        }
+
+        let (sequence, length, data) = self.slot(descriptor.slot);
+
+        // Readers of the previous message see the slot as being written from now on
+        sequence.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        unsafe {
+            ptr::copy_nonoverlapping(message.as_ptr(), data, message.len());
+        }
+
+        length.store(message.len() as u64, Ordering::Relaxed);
+        sequence.store(descriptor.sequence, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Copies the message of the descriptor, None if its slot was overwritten or is being written
+    pub fn read(&self, descriptor: &SharedMemoryDescriptor) -> Option<Vec<u8>> {
+        if descriptor.slot >= self.slots || descriptor.sequence == 0 {
+            return None;
+        }
+
+        let (sequence, length, data) = self.slot(descriptor.slot);
+
+        if sequence.load(Ordering::Acquire) != descriptor.sequence {
+            return None;
+        }
+
+        let message_length = length.load(Ordering::Relaxed);
+
+        if message_length != descriptor.length || message_length > self.capacity {
+            return None;
+        }
+
+        let mut message = vec![0u8; message_length as usize];
+
+        unsafe {
+            ptr::copy_nonoverlapping(data, message.as_mut_ptr(), message.len());
+        }
+
+        fence(Ordering::Acquire);
+
+        if sequence.load(Ordering::Relaxed) != descriptor.sequence {
+            return None;
+        }
+
+        Some(message)
+    }
+
+    /// Sequence, length and data of a slot, the slot offsets are multiples of 8 so the header can be used atomically
+    fn slot(&self, slot: u64) -> (&AtomicU64, &AtomicU64, *mut u8) {
+        let offset = SEGMENT_HEADER_LENGTH + slot * (SLOT_HEADER_LENGTH + self.capacity);
+
+        unsafe {
+            let header = self.map.as_mut_ptr().add(offset as usize);
+
+            (
+                AtomicU64::from_ptr(header as *mut u64),
+                AtomicU64::from_ptr(header.add(8) as *mut u64),
+                header.add(SLOT_HEADER_LENGTH as usize),
+            )
+        }
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        if self.owned {
+            fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+    use crate::server::protocol::{FLAG_SHARED_MEMORY, Frame};
+    use super::*;
+
+    /// Length of the messages written by the tests, filled with the low byte of their sequence
+    const MESSAGE_LENGTH: usize = 4096;
+
+    fn message_of(sequence: u64) -> Vec<u8> {
+        vec![sequence as u8; MESSAGE_LENGTH]
+    }
+
+    /// Descriptor of the given sequence, as the server hands it out
+    fn descriptor_of(segment: &Segment, sequence: u64) -> SharedMemoryDescriptor {
+        SharedMemoryDescriptor {
+            path: segment.path.clone(),
+            slot: sequence % segment.slots,
+            sequence,
+            length: MESSAGE_LENGTH as u64,
+            header: None,
+        }
+    }
+
+    #[test]
+    fn shared_memory_frames_keep_their_flag() {
+        let descriptor = SharedMemoryDescriptor {
+            path: "/dev/shm/grf-1-1.shm".into(),
+            slot: 3,
+            sequence: 11,
+            length: 1 << 20,
+            header: Some(MessageHeader {
+                sequence: 42,
+                publisher: "127.0.0.1:50000".to_string(),
+                published: Some(1_700_000_000_000),
+                received: 1_700_000_000_002,
+            }),
+        };
+
+        let bytes = Frame::shared_memory(&descriptor).to_bytes();
+        let frame = Frame::read_from(&mut bytes.as_slice()).unwrap().unwrap();
+
+        assert_eq!(frame.flags, FLAG_SHARED_MEMORY);
+        assert_eq!(frame.shared_memory_descriptor(), Some(descriptor));
+        assert_eq!(Frame::data(frame.payload).shared_memory_descriptor(), None);
+    }
+
+    #[test]
+    fn overwritten_slots_are_never_read() {
+        // Ids far from the ones of the topics, the file names are only unique within the process
+        let segment = Segment::create(u64::MAX - 1).unwrap();
+
+        let first = segment.claim(MESSAGE_LENGTH as u64).unwrap();
+        segment.write(&first, &message_of(first.sequence)).unwrap();
+        segment.release(&first, true);
+        assert_eq!(segment.read(&first), Some(message_of(first.sequence)));
+
+        // The slot comes back once every other slot was handed out
+        let mut last = first.clone();
+        while last.slot != first.slot || last.sequence == first.sequence {
+            last = segment.claim(MESSAGE_LENGTH as u64).unwrap();
+            segment.write(&last, &message_of(last.sequence)).unwrap();
+            segment.release(&last, true);
+        }
+
+        assert_eq!(segment.read(&first), None);
+        assert_eq!(segment.read(&last), Some(message_of(last.sequence)));
+
+        // A slot given up by its publisher is invalidated and handed out again
+        let abandoned = segment.claim(MESSAGE_LENGTH as u64).unwrap();
+        segment.write(&abandoned, &message_of(abandoned.sequence)).unwrap();
+        segment.release(&abandoned, false);
+        assert_eq!(segment.read(&abandoned), None);
+    }
+
+    #[test]
+    fn pending_slots_are_not_handed_out_again() {
+        let segment = Segment::create(u64::MAX - 2).unwrap();
+        assert!(segment.claim(SLOT_CAPACITY + 1).is_none());
+
+        let claimed: Vec<SharedMemoryDescriptor> = (0..SEGMENT_SLOTS).map(|_| segment.claim(1).unwrap()).collect();
+        assert!(segment.claim(1).is_none(), "Every slot waits for a commit");
+
+        segment.release(&claimed[3], false);
+        assert_eq!(segment.claim(1).unwrap().slot, claimed[3].slot);
+    }
+
+    #[test]
+    fn readers_never_see_a_slot_being_overwritten() {
+        let segment = Segment::create(u64::MAX - 3).unwrap();
+        let latest = AtomicU64::new(0);
+        let done = AtomicBool::new(false);
+
+        let (copied, missed) = thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..20_000 {
+                    let descriptor = segment.claim(MESSAGE_LENGTH as u64).unwrap();
+                    segment.write(&descriptor, &message_of(descriptor.sequence)).unwrap();
+                    segment.release(&descriptor, true);
+                    latest.store(descriptor.sequence, Ordering::Release);
+                }
+
+                done.store(true, Ordering::Release);
+            });
+
+            let reader = scope.spawn(|| {
+                let (mut copied, mut missed) = (0, 0u64);
+
+                while !done.load(Ordering::Acquire) {
+                    // Alternates the oldest message still in the segment, the next one the writer overwrites,
+                    // with one in the middle, which the writer does not reach before the read is over
+                    let age = if (copied + missed) % 2 == 0 { SEGMENT_SLOTS - 1 } else { SEGMENT_SLOTS / 2 };
+                    let sequence = latest.load(Ordering::Acquire).saturating_sub(age);
+
+                    if sequence == 0 {
+                        continue;
+                    }
+
+                    match segment.read(&descriptor_of(&segment, sequence)) {
+                        Some(message) => {
+                            assert!(message.iter().all(|byte| *byte == sequence as u8), "Torn read of message {}", sequence);
+                            copied += 1;
+                        }
+                        None => missed += 1,
+                    }
+                }
+
+                (copied, missed)
+            });
+
+            reader.join().unwrap()
+        });
+
+        assert!(copied > 0, "None of the {} reads got a message", missed);
+    }
+}
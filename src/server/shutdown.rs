@@ -0,0 +1,133 @@
+use std::fs;
+use std::net::{Shutdown, TcpStream};
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::message::message::Message;
+use crate::server::daemon::pid_file_path;
+use crate::server::persist::save_state;
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::{ServerState, topics_file_path};
+use crate::server::topic::SharedSubscriber;
+use crate::server::transport::{Connection, socket_file_path};
+
+/// How long the subscribers have to receive their queued messages before the server exits
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Files written by a running server, removed when it stops
+pub fn runtime_files(port: &str) -> Vec<PathBuf> {
+    vec![
+        topics_file_path(port),
+        pid_file_path(port),
+        socket_file_path(port),
+    ]
+}
+
+/// Closes every connection in order: subscribers get their queued messages and a close frame,
+/// then providers and waiting callers are told the server is going away
+pub fn shutdown_server(state: &ServerState, reason: &str) {
+    println!("Shutting down: {}", reason);
+    state.shutting_down.store(true, Ordering::SeqCst);
+
+    // Requests already handed to the pool, such as publications, finish before the state is saved
+    let deadline = Instant::now() + FLUSH_TIMEOUT;
+
+    while Instant::now() < deadline && state.in_flight.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // No message can be published anymore, the saved state is the final one
+    if state.persistent {
+        save_state(state);
+    }
+
+    let close = Arc::new(Frame::close(reason).to_bytes());
+
+    let subscribers: Vec<SharedSubscriber> = state.topics.lock()
+        .iter()
+        .flat_map(|topic| topic.subscribers.iter().cloned())
+        .collect();
+
+    for subscriber in &subscribers {
+        subscriber.queue.finish(Arc::clone(&close));
+    }
+
+    // Writers leave their topic once their queue is flushed
+    let deadline = Instant::now() + FLUSH_TIMEOUT;
+
+    while Instant::now() < deadline && state.topics.lock().iter().any(|topic| !topic.subscribers.is_empty()) {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    for subscriber in &subscribers {
+        subscriber.close();
+    }
+
+    state.services.close_all(reason);
+    state.actions.close_all(reason);
+
+    // Shared memory segments remove their file once dropped
+    for topic in state.topics.lock().iter_mut() {
+        topic.segment = None;
+    }
+
+    for file in runtime_files(&state.port) {
+        fs::remove_file(file).ok();
+    }
+
+    println!("Server stopped");
+}
+
+/// Shuts the server down once a stop is requested, from a request, the finish topic or a signal
+pub fn start_shutdown_thread(state: ServerState, stop_requests: Receiver<String>) {
+    thread::spawn(move || {
+        let reason = stop_requests.recv().unwrap_or("stop requested".to_string());
+
+        shutdown_server(&state, &reason);
+        exit(0);
+    });
+}
+
+/// Server side stop request
+pub fn handle_message_kind_stop(mut stream: Connection, state: ServerState) {
+    Frame::ack().write_to(&mut stream).ok();
+    stream.shutdown(Shutdown::Both).ok();
+
+    state.request_shutdown("stop requested");
+}
+
+/// Client side server stop, waits for the server to be gone
+pub fn handle_serve_stop_command(server: &str) {
+    let data = Message {
+        kind: String::from("stop"),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Could not stop the server: {}", error.message);
+        exit(1);
+    }
+
+    println!("Stopping server at {}...", server);
+
+    let deadline = Instant::now() + FLUSH_TIMEOUT * 2;
+
+    while TcpStream::connect(server).is_ok() {
+        if Instant::now() > deadline {
+            println!("Server is still running");
+            exit(1);
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    println!("Server stopped");
+}
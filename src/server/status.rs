@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::net::Shutdown;
+use std::process::exit;
+use std::sync::PoisonError;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::message::message::Message;
+use crate::server::daemon::pid_file_path;
+use crate::server::handshake::say_hello;
+use crate::server::protocol::{Frame, FrameKind, send_request};
+use crate::server::serve::ServerState;
+use crate::server::transport::{Connection, open_connection};
+
+/// State of a running server, as sent to the clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerStatus {
+    pub pid: u32,
+    pub address: String,
+    /// Seconds since the server started
+    pub uptime: u64,
+    pub topics: usize,
+    /// Connections kept open by the server: subscribers, service and action providers, pending calls and goals
+    pub clients: usize,
+}
+
+impl ServerState {
+    pub fn status(&self) -> ServerStatus {
+        let (topics, subscribers) = {
+            let topics = self.topics.lock();
+            (topics.len(), topics.iter().map(|topic| topic.subscribers.len()).sum::<usize>())
+        };
+
+        let services = self.services.lock().len() + self.services.pending.lock().unwrap_or_else(PoisonError::into_inner).len();
+        let actions = self.actions.lock().len() + self.actions.goals.lock().unwrap_or_else(PoisonError::into_inner).len();
+
+        ServerStatus {
+            pid: std::process::id(),
+            address: self.address.clone(),
+            uptime: self.started.elapsed().as_secs(),
+            topics,
+            clients: subscribers + services + actions,
+        }
+    }
+}
+
+/// Server side status request
+pub fn handle_message_kind_status(mut stream: Connection, state: ServerState) {
+    Frame::ack_with(&state.status()).write_to(&mut stream).ok();
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side server status, exits with an error if no server is running
+pub fn handle_serve_status_command(server: &str) {
+    let data = Message {
+        kind: String::from("status"),
+        ..Default::default()
+    };
+
+    let response = open_connection(server)
+        .and_then(|mut stream| {
+            stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+            say_hello(&mut stream)?;
+            send_request(&mut stream, &data)
+        });
+
+    // A server refusing the client is running all the same
+    if let Err(error) = &response {
+        if error.kind() == ErrorKind::Unsupported {
+            println!("Server at {} refused the client: {}", server, error);
+            exit(1);
+        }
+    }
+
+    let status = response
+        .ok()
+        .filter(|response| response.kind == FrameKind::Ack)
+        .and_then(|response| response.json::<ServerStatus>().ok());
+
+    let Some(status) = status else {
+        println!("No server running at {}", server);
+
+        let port = server.rsplit(':').next().unwrap_or_default();
+        if let Ok(pid) = fs::read_to_string(pid_file_path(port)) {
+            println!("Pidfile of process {} is left from a server that did not stop properly", pid.trim());
+        }
+
+        exit(1);
+    };
+
+    println!("Server:  {} (pid {})", status.address, status.pid);
+    println!("Uptime:  {}", format_uptime(status.uptime));
+    println!("Topics:  {}", status.topics);
+    println!("Clients: {}", status.clients);
+}
+
+fn format_uptime(seconds: u64) -> String {
+    let (days, hours, minutes, seconds) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+
+    if days > 0 {
+        format!("{}d {:02}h {:02}m {:02}s", days, hours, minutes, seconds)
+    }
+    else {
+        format!("{:02}h {:02}m {:02}s", hours, minutes, seconds)
+    }
+}
@@ -0,0 +1,76 @@
+use serde_json::{json, Value};
+use crate::server::protocol::{FrameKind, PROTOCOL_VERSION};
+
+/// Small xorshift generator, the cases are reproducible without pulling a fuzzing crate
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn coin(&mut self) -> bool {
+        self.next() & 1 == 0
+    }
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Vec<u8> {
+        (0..length).map(|_| self.next() as u8).collect()
+    }
+
+    pub fn string(&mut self) -> String {
+        let length = self.below(12);
+        (0..length).map(|_| char::from(b'a' + self.below(26) as u8)).collect()
+    }
+
+    pub fn json(&mut self, depth: usize) -> Value {
+        match self.below(if depth == 0 { 4 } else { 6 }) {
+            0 => Value::Null,
+            1 => Value::Bool(self.coin()),
+            2 => json!(self.next() as i64),
+            3 => Value::String(self.string()),
+            4 => Value::Array((0..self.below(4)).map(|_| self.json(depth - 1)).collect()),
+            _ => Value::Object((0..self.below(4)).map(|_| (self.string(), self.json(depth - 1))).collect()),
+        }
+    }
+}
+
+/// Request kinds the server knows, followed by an unknown one
+pub const KINDS: [&str; 22] = [
+    "sub", "unsub", "pub", "list", "srv", "call", "srv_list", "action", "goal", "cancel", "action_list",
+    "param_get", "param_set", "param_delete", "param_load", "stop", "status", "topic_delete", "topic_clear",
+    "topic_kick", "topic_info", "bogus",
+];
+
+pub fn frame_bytes(version: u8, kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![version, kind, 0];
+    bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+pub fn request_bytes(message: &Value) -> Vec<u8> {
+    frame_bytes(PROTOCOL_VERSION, FrameKind::Request as u8, &serde_json::to_vec(message).unwrap())
+}
+
+/// Well-formed request of the given kind, with every field its kind requires
+pub fn valid_request(rng: &mut Rng, kind: &str) -> Value {
+    json!({
+        "kind": kind,
+        "topic": rng.string(),
+        "message_type": null,
+        "message": rng.json(3),
+        "subscriber": rng.next(),
+        "service": rng.string(),
+        "action": rng.string(),
+        "goal": rng.next(),
+        "key": rng.string(),
+        "client": rng.string(),
+    })
+}
@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::{IpAddr, Shutdown};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Instant;
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::message::message::{load_schema, TopicInfo};
+use crate::server::handshake::Session;
+use crate::server::info::{INFO_TOPIC, publish_event, SystemEvent, timestamp_millis};
+use crate::server::param::PARAMETERS_TOPIC;
+use crate::server::protocol::{Frame, HEADER_LENGTH, MessageHeader};
+use crate::server::qos::{OutboundQueue, QoS};
+use crate::server::serve::AtomicTopics;
+use crate::server::compression::Compression;
+use crate::server::encoding::Encoding;
+use crate::server::shared_memory::{Segment, SharedMemoryDescriptor};
+use crate::server::transport::Connection;
+
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(1);
+
+static NEXT_SEGMENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Topics created by the server, they cannot be deleted
+pub const SYSTEM_TOPICS: [&str; 3] = ["finish", INFO_TOPIC, PARAMETERS_TOPIC];
+
+/// Subscriber connection, messages are queued and written to its stream by a dedicated thread
+pub struct Subscriber {
+    pub id: u64,
+    /// Name and connection number of the subscriber, as given by its session
+    pub client: String,
+    /// Name the subscriber gave in its hello
+    pub name: String,
+    /// Address of the subscriber, kept as it cannot be read once the connection is lost
+    pub address: String,
+    /// Only written to by the writer thread, can be shut down from anywhere
+    pub stream: Connection,
+    pub qos: QoS,
+    pub queue: OutboundQueue,
+    /// Whether the subscriber only gets descriptors of the messages published through shared memory
+    pub shared_memory: bool,
+    /// Encoding of the messages sent to the subscriber
+    pub encoding: Encoding,
+    /// Compression of the large messages sent to the subscriber
+    pub compression: Compression,
+    /// Whether the messages are sent along with their header
+    pub header: bool,
+}
+
+/// Subscriber shared between its topic and the connections publishing to it
+pub type SharedSubscriber = Arc<Subscriber>;
+
+/// Response to a successful sub request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Subscription {
+    /// Identifier to give back in order to unsubscribe
+    pub subscriber: u64,
+    /// QoS granted by the server
+    pub qos: QoS,
+    /// Whether large messages from local publishers are read from shared memory
+    #[serde(default)]
+    pub shared_memory: bool,
+    /// Encoding of the data frames sent to the subscriber
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// Compression of the data frames sent to the subscriber, small ones are not compressed
+    #[serde(default)]
+    pub compression: Compression,
+    /// Whether the data frames carry the header of the messages
+    #[serde(default)]
+    pub header: bool,
+}
+
+/// Response to a topic_info request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TopicDetails {
+    #[serde(flatten)]
+    pub info: TopicInfo,
+    /// Subscribers of the topic, with what they negotiated
+    pub connections: Vec<SubscriberInfo>,
+    pub traffic: TrafficInfo,
+}
+
+/// Description of a subscriber for the clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SubscriberInfo {
+    pub id: u64,
+    pub client: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub address: String,
+    pub qos: QoS,
+    pub encoding: Encoding,
+    pub compression: Compression,
+    pub shared_memory: bool,
+    #[serde(default)]
+    pub header: bool,
+    /// Messages dropped because the queue of the subscriber was full
+    #[serde(default)]
+    pub dropped: u64,
+}
+
+/// Amount of data sent to the subscribers of a topic since it was created
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TrafficInfo {
+    /// Messages published on the topic
+    pub messages: u64,
+    /// Bytes of the frames queued for the subscribers, had they not been compressed
+    pub raw_bytes: u64,
+    /// Bytes of the frames actually queued for the subscribers
+    pub sent_bytes: u64,
+    /// Age of the topic in seconds, the counters are averaged over it
+    pub seconds: f64,
+}
+
+/// Counters of the data sent to the subscribers of a topic, updated without locking the topics
+#[derive(Default)]
+pub struct Traffic {
+    messages: AtomicU64,
+    raw_bytes: AtomicU64,
+    sent_bytes: AtomicU64,
+}
+
+/// Response to the topic admin requests
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Disconnected {
+    /// Ids of the subscribers that were disconnected
+    pub subscribers: Vec<u64>,
+}
+
+/// Server side topic
+pub struct Topic {
+    pub name: String,
+    pub message_type: Option<String>,
+    pub subscribers: Vec<SharedSubscriber>,
+    /// Schema published messages are validated against, None if the topic is untyped or validation is disabled
+    pub schema: Option<Arc<JSONSchema>>,
+    /// Whether the last published message is kept for new subscribers
+    pub latched: bool,
+    /// Last message published on a latched topic
+    pub retained: Option<Vec<u8>>,
+    /// Header of the retained message
+    pub retained_header: Option<MessageHeader>,
+    /// Last sequence number handed out, locked while a message is queued so that subscribers get them in order
+    pub sequence: Arc<Mutex<u64>>,
+    /// Hosts that published on the topic, publishers connect once per message
+    pub publishers: HashSet<IpAddr>,
+    /// QoS granted to every subscriber, None if they get the one they ask for
+    pub qos: Option<QoS>,
+    pub access: TopicAccess,
+    /// Whether the topic comes from the server configuration, its message type cannot be changed
+    pub declared: bool,
+    /// Ring buffer the large messages of local publishers go through, created by the first of them
+    pub segment: Option<Arc<Segment>>,
+    pub traffic: Arc<Traffic>,
+    pub created: Instant,
+}
+
+/// Hosts allowed to use a topic, None allows everyone
+#[derive(Clone, Debug, Default)]
+pub struct TopicAccess {
+    pub publishers: Option<HashSet<IpAddr>>,
+    pub subscribers: Option<HashSet<IpAddr>>,
+}
+
+impl Subscriber {
+    pub fn new(stream: Connection, session: &Session, qos: QoS, shared_memory: bool, encoding: Encoding, compression: Compression, header: bool) -> SharedSubscriber {
+        Arc::new(Subscriber {
+            id: NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed),
+            client: session.client(),
+            name: session.hello.client.clone(),
+            address: session.address.clone(),
+            stream,
+            qos,
+            queue: OutboundQueue::new(qos),
+            shared_memory,
+            encoding,
+            compression,
+            header,
+        })
+    }
+
+    /// Starts writing the queued messages to the subscriber, it is removed from its topic once its connection is lost
+    pub fn start_writer(self: &Arc<Self>, topic_name: String, topics: AtomicTopics) {
+        let subscriber = Arc::clone(self);
+
+        thread::spawn(move || {
+            while let Some(frame) = subscriber.queue.pop() {
+                if (&subscriber.stream).write_all(&frame).is_err() {
+                    break;
+                }
+            }
+
+            subscriber.close();
+
+            let removed = topics.lock()
+                .iter_mut()
+                .find(|topic| topic.name == topic_name)
+                .and_then(|topic| topic.remove_subscriber(subscriber.id));
+
+            // Subscribers removed by an unsub or the shutdown are already gone
+            if removed.is_some() {
+                println!("Dropped closed subscriber {} from topic {} ({} messages dropped)", subscriber.id, topic_name, subscriber.queue.dropped());
+
+                publish_event(&topics, SystemEvent::SubscriptionRemoved {
+                    topic: topic_name.clone(),
+                    subscriber: subscriber.id,
+                });
+                publish_event(&topics, SystemEvent::ClientDisconnected {
+                    client: subscriber.client.clone(),
+                    reason: format!("subscriber {} of topic {} is gone", subscriber.id, topic_name),
+                });
+            }
+        });
+    }
+
+    /// Sends the pending messages then a close frame with the reason, the writer closes the connection after it
+    pub fn disconnect(&self, reason: &str) {
+        self.queue.finish(Arc::new(Frame::close(reason).to_bytes()));
+    }
+
+    /// Description of the subscriber for the clients
+    pub fn info(&self) -> SubscriberInfo {
+        SubscriberInfo {
+            id: self.id,
+            client: self.client.clone(),
+            name: self.name.clone(),
+            address: self.address.clone(),
+            qos: self.qos,
+            encoding: self.encoding,
+            compression: self.compression,
+            shared_memory: self.shared_memory,
+            header: self.header,
+            dropped: self.queue.dropped(),
+        }
+    }
+
+    /// Whether the subscriber is designated by the given subscriber id, client name, name and connection number, address or IP
+    pub fn matches(&self, client: &str) -> bool {
+        self.id.to_string() == client
+            || self.client == client
+            || self.name == client
+            || self.address == client
+            || self.address.rsplit_once(':').is_some_and(|(ip, _)| ip == client)
+    }
+
+    /// Closes the subscriber connection, its client will see the end of the stream
+    pub fn close(&self) {
+        self.queue.close();
+        self.stream.shutdown(Shutdown::Both).ok();
+    }
+}
+
+impl Topic {
+    pub fn new(name: &str, message_type: Option<String>) -> Topic {
+        Topic {
+            name: name.to_string(),
+            message_type,
+            subscribers: vec![],
+            schema: None,
+            latched: false,
+            retained: None,
+            retained_header: None,
+            sequence: Arc::new(Mutex::new(0)),
+            publishers: HashSet::new(),
+            qos: None,
+            access: TopicAccess::default(),
+            declared: false,
+            segment: None,
+            traffic: Arc::new(Traffic::default()),
+            created: Instant::now(),
+        }
+    }
+
+    /// Creates a typed topic validating its messages against the schema of its message type
+    pub fn with_validation(name: &str, message_type: String) -> Result<Topic, String> {
+        let schema = load_schema(&message_type)?;
+
+        let mut topic = Topic::new(name, Some(message_type));
+        topic.schema = Some(Arc::new(schema));
+
+        Ok(topic)
+    }
+
+    /// Makes the topic keep its last message for new subscribers
+    pub fn latched(mut self, latched: bool) -> Topic {
+        self.latched = latched;
+        self
+    }
+
+    /// Description of the topic for the clients
+    pub fn info(&self) -> TopicInfo {
+        TopicInfo {
+            name: self.name.clone(),
+            message_type: self.message_type.clone(),
+            latched: self.latched,
+            subscribers: self.subscribers.len(),
+            publishers: self.publishers.len(),
+        }
+    }
+
+    /// Description of the topic with its subscribers and traffic
+    pub fn details(&self) -> TopicDetails {
+        TopicDetails {
+            info: self.info(),
+            connections: self.subscribers.iter().map(|subscriber| subscriber.info()).collect(),
+            traffic: self.traffic.info(self.created.elapsed().as_secs_f64()),
+        }
+    }
+
+    /// Whether the message type of the topic can be replaced, system and declared topics keep theirs
+    pub fn is_retypable(&self) -> bool {
+        self.subscribers.is_empty() && !self.declared && !SYSTEM_TOPICS.contains(&self.name.as_str())
+    }
+
+    /// Shared memory segment of the topic, created on first use
+    pub fn shared_segment(&mut self) -> std::io::Result<Arc<Segment>> {
+        if self.segment.is_none() {
+            self.segment = Some(Arc::new(Segment::create(NEXT_SEGMENT_ID.fetch_add(1, Ordering::Relaxed))?));
+        }
+
+        Ok(Arc::clone(self.segment.as_ref().unwrap()))
+    }
+
+    /// Removes the subscriber with the given id, returns it if it was found
+    pub fn remove_subscriber(&mut self, id: u64) -> Option<SharedSubscriber> {
+        let index = self.subscribers.iter().position(|subscriber| subscriber.id == id)?;
+
+        Some(self.subscribers.remove(index))
+    }
+}
+
+/// Validates a message against a topic schema, returns the validation errors if any
+pub fn validate_message(schema: &JSONSchema, message: Option<&Value>) -> Result<(), Vec<String>> {
+    let Some(message) = message else {
+        return Err(vec!["Message is empty".to_string()]);
+    };
+
+    schema.validate(message).map_err(|errors| {
+        errors
+            .map(|error| {
+                let path = error.instance_path.to_string();
+                format!("{}: {}", if path.is_empty() { "/" } else { path.as_str() }, error)
+            })
+            .collect()
+    })
+}
+
+/// Disconnects subscribers removed from their topic by an admin request, returns their ids
+pub fn disconnect_subscribers(topics: &AtomicTopics, topic_name: &str, subscribers: Vec<SharedSubscriber>, reason: &str) -> Vec<u64> {
+    for subscriber in &subscribers {
+        subscriber.disconnect(reason);
+
+        publish_event(topics, SystemEvent::SubscriptionRemoved {
+            topic: topic_name.to_string(),
+            subscriber: subscriber.id,
+        });
+    }
+
+    subscribers.iter().map(|subscriber| subscriber.id).collect()
+}
+
+impl TopicAccess {
+    pub fn can_publish(&self, host: Option<IpAddr>) -> bool {
+        Self::allows(self.publishers.as_ref(), host)
+    }
+
+    pub fn can_subscribe(&self, host: Option<IpAddr>) -> bool {
+        Self::allows(self.subscribers.as_ref(), host)
+    }
+
+    fn allows(hosts: Option<&HashSet<IpAddr>>, host: Option<IpAddr>) -> bool {
+        match hosts {
+            Some(hosts) => host.is_some_and(|host| hosts.contains(&host)),
+            None => true,
+        }
+    }
+}
+
+/// Frame ready to be queued, along with its length before compression
+type QueuedFrame = (Arc<Vec<u8>>, u64);
+
+/// Message sent to the subscribers of a topic, its data frame is only built once for each encoding they use
+pub struct Outgoing {
+    /// JSON content of the message, empty for untyped topics
+    payload: Vec<u8>,
+    /// Address of the publisher, `server` for the messages of the server itself
+    publisher: String,
+    /// When the publisher sent the message, if it told, and when the server received it
+    published: Option<u64>,
+    received: u64,
+    /// Stamped once the message gets its sequence number
+    header: Option<MessageHeader>,
+    /// Descriptor of a message in shared memory, for the subscribers reading it in its encoding
+    shared: Option<(SharedMemoryDescriptor, Encoding)>,
+    /// Descriptor frames, with and without the header
+    descriptors: HashMap<bool, QueuedFrame>,
+    /// Data frames, None for the encodings the message could not be converted to
+    frames: HashMap<(Encoding, Compression, bool), Option<QueuedFrame>>,
+}
+
+impl Outgoing {
+    /// Message published by the server itself
+    pub fn new(payload: Vec<u8>) -> Outgoing {
+        Outgoing {
+            payload,
+            publisher: "server".to_string(),
+            published: None,
+            received: timestamp_millis(),
+            header: None,
+            shared: None,
+            descriptors: HashMap::new(),
+            frames: HashMap::new(),
+        }
+    }
+
+    /// Message written in shared memory in the given encoding, along with its JSON content
+    pub fn shared(payload: Vec<u8>, descriptor: SharedMemoryDescriptor, encoding: Encoding) -> Outgoing {
+        Outgoing {
+            shared: Some((descriptor, encoding)),
+            ..Outgoing::new(payload)
+        }
+    }
+
+    /// Message received from a client, at the given time
+    pub fn published_by(mut self, publisher: String, published: Option<u64>, received: u64) -> Outgoing {
+        self.publisher = publisher;
+        self.published = published;
+        self.received = received;
+        self
+    }
+
+    /// Gives the message its sequence number, returns its header
+    pub fn stamp(&mut self, sequence: u64) -> MessageHeader {
+        let header = MessageHeader {
+            sequence,
+            publisher: self.publisher.clone(),
+            published: self.published,
+            received: self.received,
+        };
+
+        self.header = Some(header.clone());
+        header
+    }
+
+    /// Message sent again with the header it was given, for the late joiners of latched topics
+    pub fn with_header(mut self, header: MessageHeader) -> Outgoing {
+        self.header = Some(header);
+        self
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Frame to queue for the subscriber, along with its length before compression, None if it cannot be built
+    pub fn frame_for(&mut self, subscriber: &Subscriber) -> Option<QueuedFrame> {
+        let header = self.header.as_ref().filter(|_| subscriber.header);
+
+        let (frame, raw_length) = match &self.shared {
+            Some((descriptor, encoding)) if subscriber.shared_memory && subscriber.encoding == *encoding => {
+                self.descriptors.entry(header.is_some()).or_insert_with(|| {
+                    let descriptor = SharedMemoryDescriptor {
+                        header: header.cloned(),
+                        ..descriptor.clone()
+                    };
+                    let frame = Frame::shared_memory(&descriptor).encoded(*encoding).to_bytes();
+                    let length = frame.len() as u64;
+
+                    (Arc::new(frame), length)
+                })
+            }
+            _ => {
+                let payload = &self.payload;
+
+                let frame = self.frames.entry((subscriber.encoding, subscriber.compression, header.is_some())).or_insert_with(|| {
+                    let frame = match header {
+                        Some(header) => Frame::data_with_header(header, payload, subscriber.encoding),
+                        None => subscriber.encoding.encode_json(payload).map(|payload| Frame::data(payload).encoded(subscriber.encoding)),
+                    };
+
+                    // Only logged once per message and encoding, the subscribers of that encoding do not get the message
+                    let frame = frame
+                        .map_err(|error| println!("Could not convert a message to {:?}, it is not sent to its subscribers: {}", subscriber.encoding, error))
+                        .ok()?;
+                    let raw_length = (HEADER_LENGTH + frame.payload.len()) as u64;
+
+                    Some((Arc::new(frame.compressed(subscriber.compression).to_bytes()), raw_length))
+                });
+
+                frame.as_mut()?
+            }
+        };
+
+        Some((Arc::clone(frame), *raw_length))
+    }
+}
+
+impl Traffic {
+    /// Counts a message sent as frames of the given lengths, before and after compression
+    fn record(&self, raw_bytes: u64, sent_bytes: u64) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.raw_bytes.fetch_add(raw_bytes, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(sent_bytes, Ordering::Relaxed);
+    }
+
+    fn info(&self, seconds: f64) -> TrafficInfo {
+        TrafficInfo {
+            messages: self.messages.load(Ordering::Relaxed),
+            raw_bytes: self.raw_bytes.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            seconds,
+        }
+    }
+}
+
+/// Queues the message for every subscriber, in their encoding and compression, returns the ids of the ones that are closed
+///
+/// Subscribers reading shared memory get the descriptor of the message instead, if it is there
+pub fn write_to_subscribers(subscribers: &[SharedSubscriber], message: &mut Outgoing, traffic: &Traffic) -> Vec<u64> {
+    let mut dead_subscribers = vec![];
+    let mut raw_bytes = 0;
+    let mut sent_bytes = 0;
+
+    for subscriber in subscribers {
+        let Some((frame, raw_length)) = message.frame_for(subscriber) else {
+            continue;
+        };
+        let length = frame.len() as u64;
+
+        if subscriber.queue.push(frame) {
+            raw_bytes += raw_length;
+            sent_bytes += length;
+        }
+        else {
+            dead_subscribers.push(subscriber.id);
+        }
+    }
+
+    traffic.record(raw_bytes, sent_bytes);
+
+    dead_subscribers
+}
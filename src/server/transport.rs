@@ -0,0 +1,138 @@
+use std::io::{Read, Result, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::get_temp_folder;
+
+/// Connection between a client and the server, over TCP or the local socket of the server
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Local socket of the server listening on the given port
+pub fn socket_file_path(port: &str) -> PathBuf {
+    PathBuf::from(get_temp_folder().unwrap()).join(format!("grf-{}.sock", port))
+}
+
+/// Local socket to use for the given server address, None if the server is not on this host
+fn local_socket_path(server: &str) -> Option<PathBuf> {
+    let (host, port) = server.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let is_local = host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+
+    if !is_local || get_temp_folder().is_err() {
+        return None;
+    }
+
+    Some(socket_file_path(port))
+}
+
+/// Opens a connection to the server, through its local socket when it runs on this host
+pub fn open_connection(server: &str) -> Result<Connection> {
+    #[cfg(unix)]
+    if let Some(path) = local_socket_path(server) {
+        if let Ok(stream) = UnixStream::connect(path) {
+            return Ok(Connection::Unix(stream));
+        }
+    }
+
+    TcpStream::connect(server).map(Connection::Tcp)
+}
+
+impl Connection {
+    pub fn try_clone(&self) -> Result<Connection> {
+        match self {
+            Connection::Tcp(stream) => stream.try_clone().map(Connection::Tcp),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.try_clone().map(Connection::Unix),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    /// Address of the peer, local socket peers have none
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Connection::Tcp(stream) => stream.peer_addr().ok(),
+            #[cfg(unix)]
+            Connection::Unix(_) => None,
+        }
+    }
+
+    /// Host of the peer for the access rules, local socket peers are on the loopback
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Connection::Tcp(stream) => stream.peer_addr().ok().map(|address| address.ip()),
+            #[cfg(unix)]
+            Connection::Unix(_) => Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST)),
+        }
+    }
+
+    /// Whether both ends are on the same host, so that they can share memory
+    pub fn is_local(&self) -> bool {
+        self.peer_ip().is_some_and(|ip| ip.is_loopback())
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl Read for &Connection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => (&*stream).read(buf),
+        }
+    }
+}
+
+impl Write for &Connection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => (&*stream).flush(),
+        }
+    }
+}
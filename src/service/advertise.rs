@@ -0,0 +1,105 @@
+use std::net::Shutdown;
+use std::sync::PoisonError;
+use std::thread;
+use crate::message::message::Message;
+use crate::server::handshake::Session;
+use crate::server::info::{publish_event, SystemEvent};
+use crate::server::protocol::{clone_connection, Frame, FrameKind};
+use crate::server::service::{AtomicServices, Service, ServiceInfo};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::validate_message;
+use crate::server::transport::Connection;
+
+/// Server side service advertisement
+pub fn handle_message_kind_srv(mut stream: Connection, message: Message, session: &Session, services: AtomicServices, topics: AtomicTopics) {
+    let service_name = message.service.unwrap();
+
+    let info = ServiceInfo {
+        name: service_name.clone(),
+        request_type: message.request_type,
+        response_type: message.response_type,
+    };
+
+    let Some(provider_stream) = clone_connection(&mut stream) else {
+        return;
+    };
+
+    let service = {
+        let mut services_list = services.lock();
+
+        if services_list.iter().any(|service| service.info.name == service_name) {
+            Err(Frame::error("service_exists", format!("Service \"{}\" is already advertised", service_name)))
+        }
+        else {
+            match Service::new(info, provider_stream) {
+                Ok(service) => {
+                    let provider = service.provider.clone();
+                    services_list.push(service);
+                    Ok(provider)
+                }
+                Err(error) => Err(Frame::error("unknown_message_type", error)),
+            }
+        }
+    };
+
+    match service {
+        Ok(provider) => {
+            // Calls are written to the same connection, the provider must get its acknowledgement first
+            Frame::ack().write_to(&mut *provider.lock().unwrap_or_else(PoisonError::into_inner)).ok();
+            println!("Advertised service {}", service_name);
+
+            // The provider connection lives as long as the service, it does not hold a worker
+            let client = session.client();
+            thread::spawn(move || read_provider_replies(stream, client, service_name, services, topics));
+        }
+        Err(error) => {
+            error.write_to(&mut stream).ok();
+        }
+    }
+}
+
+/// Forwards the responses of a provider to the callers, until its connection is closed
+fn read_provider_replies(mut stream: Connection, client: String, service_name: String, services: AtomicServices, topics: AtomicTopics) {
+    while let Ok(Some(frame)) = Frame::read_from(&mut stream) {
+        if frame.kind != FrameKind::Request {
+            continue;
+        }
+
+        let reply: Message = match frame.json() {
+            Ok(reply) => reply,
+            Err(error) => {
+                println!("Malformed reply from service {}: {}", service_name, error);
+                continue;
+            }
+        };
+
+        let Some(mut pending_call) = reply.call.and_then(|call| services.take_pending_call(call)) else {
+            println!("Service {} replied to an unknown call", service_name);
+            continue;
+        };
+
+        let schema = services.lock()
+            .iter()
+            .find(|service| service.info.name == service_name)
+            .and_then(|service| service.response_schema.clone());
+
+        let response = match schema.map(|schema| validate_message(&schema, reply.message.as_ref())) {
+            Some(Err(errors)) => {
+                let error = format!("Service \"{}\" sent a badly formatted response", service_name);
+                Frame::error_with_details("invalid_response", error, errors)
+            }
+            _ => Frame::ack_with(&reply.message),
+        };
+
+        response.write_to(&mut pending_call.caller).ok();
+        pending_call.caller.shutdown(Shutdown::Both).ok();
+    }
+
+    services.remove_service(&service_name);
+    println!("Service {} is no longer available", service_name);
+
+    publish_event(&topics, SystemEvent::ClientDisconnected {
+        client,
+        reason: format!("provider of service {} is gone", service_name),
+    });
+}
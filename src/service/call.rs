@@ -0,0 +1,102 @@
+use std::net::Shutdown;
+use std::process::exit;
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
+use crate::message::message::Message;
+use crate::server::protocol::{clone_connection, connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::service::{AtomicServices, CALL_TIMEOUT, MAX_CALL_TIMEOUT, ServiceCall};
+use crate::server::topic::validate_message;
+use crate::server::transport::Connection;
+
+/// Server side service call
+pub fn handle_message_kind_call(mut stream: Connection, message: Message, services: AtomicServices) {
+    let service_name = message.service.clone().unwrap();
+
+    let service = services.lock()
+        .iter()
+        .find(|service| service.info.name == service_name)
+        .map(|service| (service.request_schema.clone(), Arc::clone(&service.provider)));
+
+    let Some((schema, provider)) = service else {
+        let error = format!("Service \"{}\" not found", service_name);
+        Frame::error("unknown_service", error).write_to(&mut stream).ok();
+        return;
+    };
+
+    if let Some(schema) = schema {
+        if let Err(errors) = validate_message(&schema, message.message.as_ref()) {
+            let error = format!("Wrong request format for service \"{}\"", service_name);
+            Frame::error_with_details("invalid_request", error, errors).write_to(&mut stream).ok();
+            return;
+        }
+    }
+
+    // The caller connection is answered by the provider reader once the response arrives
+    let Some(caller) = clone_connection(&mut stream) else {
+        return;
+    };
+
+    let timeout = message.timeout.map_or(CALL_TIMEOUT, |timeout| Duration::from_secs(timeout.min(MAX_CALL_TIMEOUT.as_secs())));
+    let call = services.add_pending_call(&service_name, caller, timeout);
+
+    let service_call = ServiceCall {
+        call,
+        request: message.message,
+    };
+
+    let forwarded = Frame::data(serde_json::to_vec(&service_call).unwrap())
+        .write_to(&mut *provider.lock().unwrap_or_else(PoisonError::into_inner))
+        .is_ok();
+
+    if forwarded {
+        println!("Forwarded call {} to service {}", call, service_name);
+    }
+    else if let Some(mut pending_call) = services.take_pending_call(call) {
+        let error = format!("Service \"{}\" is no longer available", service_name);
+        Frame::error("service_unavailable", error).write_to(&mut pending_call.caller).ok();
+        pending_call.caller.shutdown(Shutdown::Both).ok();
+    }
+}
+
+/// Client side service call
+pub fn handle_service_call_command(service_name: String, request: Option<String>, timeout: u64, server: &str) {
+    let content = request.map(|request| {
+        serde_json::from_str(&request).unwrap_or_else(|error| {
+            println!("Could not parse request to JSON: {}", error);
+            exit(1);
+        })
+    });
+
+    let data = Message {
+        kind: String::from("call"),
+        service: Some(service_name.clone()),
+        message: content,
+        timeout: Some(timeout),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    // The server gives up on the provider at the timeout, the margin lets its error arrive first
+    stream.set_read_timeout(Some(Duration::from_secs(timeout.saturating_add(1)))).ok();
+
+    let response = match send_request(&mut stream, &data) {
+        Ok(response) => response,
+        Err(error) => {
+            println!("No response from service \"{}\": {}", service_name, error);
+            exit(1);
+        }
+    };
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Call failed: {}", error.message);
+
+        for detail in error.details {
+            println!("  - {}", detail);
+        }
+
+        exit(1);
+    }
+
+    println!("{}", String::from_utf8_lossy(&response.payload));
+}
@@ -0,0 +1,22 @@
+use std::process::exit;
+use crate::message::message::get_default;
+use crate::service::list::get_services;
+
+/// Client side service info
+pub fn handle_service_info_command(service_name: String, server: &str) {
+    let services = get_services(server);
+
+    let Some(service) = services.into_iter().find(|service| service.name == service_name) else {
+        println!("Service \"{}\" not found", service_name);
+        exit(1);
+    };
+
+    println!("Service:       {}", service.name);
+    println!("Request type:  {}", service.request_type.clone().unwrap_or("None".to_string()));
+    println!("Response type: {}", service.response_type.unwrap_or("None".to_string()));
+
+    if let Some(default_request) = service.request_type.and_then(get_default) {
+        println!("Request example:");
+        println!("{}", default_request);
+    }
+}
@@ -0,0 +1,66 @@
+use std::net::Shutdown;
+use std::process::exit;
+use crate::message::message::Message;
+use crate::server::protocol::{connect, Frame, FrameKind, send_request};
+use crate::server::service::{AtomicServices, ServiceInfo};
+use crate::server::transport::Connection;
+
+/// Server side service list
+pub fn handle_message_kind_srv_list(mut stream: Connection, services: AtomicServices) {
+    let services_info: Vec<ServiceInfo> = services.lock()
+        .iter()
+        .map(|service| service.info.clone())
+        .collect();
+
+    Frame::ack_with(&services_info).write_to(&mut stream).ok();
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Retrieves the services advertised on the server
+pub fn get_services(server: &str) -> Vec<ServiceInfo> {
+    let data = Message {
+        kind: String::from("srv_list"),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind != FrameKind::Ack {
+        println!("Could not retrieve the services list");
+        exit(1);
+    }
+
+    response.json().expect("Malformed services list")
+}
+
+/// Client side service list
+pub fn handle_service_list_command(with_message_types: bool, server: &str) {
+    let services = get_services(server);
+
+    let mut separator = "--------------------".to_string();
+
+    print!("{0: <20}", "Service name");
+
+    if with_message_types {
+        print!("{0: <20}{1: <20}", "Request type", "Response type");
+        separator += "----------------------------------------";
+    }
+
+    println!();
+    println!("{separator}");
+
+    for service in services {
+        print!("{0: <20}", service.name);
+
+        if with_message_types {
+            print!(
+                "{0: <20}{1: <20}",
+                service.request_type.unwrap_or("None".to_string()),
+                service.response_type.unwrap_or("None".to_string())
+            );
+        }
+
+        println!();
+    }
+}
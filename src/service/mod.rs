@@ -0,0 +1,4 @@
+pub mod advertise;
+pub mod call;
+pub mod list;
+pub mod info;
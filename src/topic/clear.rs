@@ -0,0 +1,80 @@
+use std::mem;
+use std::net::Shutdown;
+use std::process::exit;
+use crate::confirm;
+use crate::message::message::{get_topics, Message};
+use crate::server::info::{publish_event, SystemEvent};
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::{disconnect_subscribers, Disconnected};
+use crate::server::transport::Connection;
+
+/// Server side topic clear, drops the retained message and disconnects the subscribers, the topic itself is kept
+pub fn handle_message_kind_topic_clear(mut stream: Connection, message: Message, topics: AtomicTopics) {
+    let topic_name = message.topic.unwrap();
+
+    let cleared = topics.lock()
+        .iter_mut()
+        .find(|topic| topic.name == topic_name)
+        .map(|topic| {
+            topic.retained = None;
+            topic.retained_header = None;
+            topic.publishers.clear();
+            mem::take(&mut topic.subscribers)
+        });
+
+    match cleared {
+        Some(subscribers) => {
+            let subscribers = disconnect_subscribers(&topics, &topic_name, subscribers, "topic cleared");
+            Frame::ack_with(&Disconnected { subscribers }).write_to(&mut stream).ok();
+            println!("Cleared topic {}", topic_name);
+
+            publish_event(&topics, SystemEvent::TopicCleared {
+                topic: topic_name,
+            });
+        }
+        None => {
+            let error = format!("Topic \"{}\" not found", topic_name);
+            Frame::error("unknown_topic", error).write_to(&mut stream).ok();
+        }
+    }
+
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side topic clear, asks for a confirmation unless told not to
+pub fn handle_topic_clear_command(topic_name: String, yes: bool, server: &str) {
+    let Some(topic) = get_topics(server).into_iter().find(|topic| topic.name == topic_name) else {
+        println!("Topic \"{}\" not found", topic_name);
+        exit(1);
+    };
+
+    let question = format!(
+        "Clear topic \"{}\", dropping its retained message and disconnecting its {} subscribers?",
+        topic_name,
+        topic.subscribers
+    );
+
+    if !yes && !confirm(&question) {
+        println!("Topic not cleared");
+        exit(1);
+    }
+
+    let data = Message {
+        kind: String::from("topic_clear"),
+        topic: Some(topic_name.clone()),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Could not clear topic: {}", error.message);
+        exit(1);
+    }
+
+    let disconnected: Disconnected = response.json().expect("Malformed clear response");
+    println!("Cleared topic \"{}\", {} subscribers disconnected", topic_name, disconnected.subscribers.len());
+}
@@ -0,0 +1,78 @@
+use std::net::Shutdown;
+use std::process::exit;
+use crate::confirm;
+use crate::message::message::{get_topics, Message};
+use crate::server::info::{publish_event, SystemEvent};
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::{disconnect_subscribers, Disconnected, SYSTEM_TOPICS};
+use crate::server::transport::Connection;
+
+/// Server side topic delete, the subscribers of the topic are disconnected
+pub fn handle_message_kind_topic_delete(mut stream: Connection, message: Message, topics: AtomicTopics) {
+    let topic_name = message.topic.unwrap();
+
+    let deleted = if SYSTEM_TOPICS.contains(&topic_name.as_str()) {
+        Err(Frame::error("system_topic", format!("Topic \"{}\" is a system topic and cannot be deleted", topic_name)))
+    }
+    else {
+        let mut topics_list = topics.lock();
+
+        match topics_list.iter().position(|topic| topic.name == topic_name) {
+            Some(index) => Ok(topics_list.remove(index)),
+            None => Err(Frame::error("unknown_topic", format!("Topic \"{}\" not found", topic_name))),
+        }
+    };
+
+    match deleted {
+        Ok(topic) => {
+            topics.topics_to_file();
+
+            let subscribers = disconnect_subscribers(&topics, &topic_name, topic.subscribers, "topic deleted");
+            Frame::ack_with(&Disconnected { subscribers }).write_to(&mut stream).ok();
+            println!("Deleted topic {}", topic_name);
+
+            publish_event(&topics, SystemEvent::TopicDeleted {
+                topic: topic_name,
+            });
+        }
+        Err(error) => {
+            error.write_to(&mut stream).ok();
+        }
+    }
+
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side topic delete, asks for a confirmation unless told not to
+pub fn handle_topic_delete_command(topic_name: String, yes: bool, server: &str) {
+    let Some(topic) = get_topics(server).into_iter().find(|topic| topic.name == topic_name) else {
+        println!("Topic \"{}\" not found", topic_name);
+        exit(1);
+    };
+
+    let question = format!("Delete topic \"{}\" and disconnect its {} subscribers?", topic_name, topic.subscribers);
+
+    if !yes && !confirm(&question) {
+        println!("Topic not deleted");
+        exit(1);
+    }
+
+    let data = Message {
+        kind: String::from("topic_delete"),
+        topic: Some(topic_name.clone()),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Could not delete topic: {}", error.message);
+        exit(1);
+    }
+
+    let disconnected: Disconnected = response.json().expect("Malformed delete response");
+    println!("Deleted topic \"{}\", {} subscribers disconnected", topic_name, disconnected.subscribers.len());
+}
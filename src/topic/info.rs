@@ -0,0 +1,97 @@
+use std::net::Shutdown;
+use std::process::exit;
+use clap::ValueEnum;
+use crate::message::message::Message;
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::TopicDetails;
+use crate::server::transport::Connection;
+
+/// Server side topic info
+pub fn handle_message_kind_topic_info(mut stream: Connection, message: Message, topics: AtomicTopics) {
+    let topic_name = message.topic.unwrap();
+
+    let details = topics.lock()
+        .iter()
+        .find(|topic| topic.name == topic_name)
+        .map(|topic| topic.details());
+
+    match details {
+        Some(details) => Frame::ack_with(&details).write_to(&mut stream).ok(),
+        None => Frame::error("unknown_topic", format!("Topic \"{}\" not found", topic_name)).write_to(&mut stream).ok(),
+    };
+
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side topic info
+pub fn handle_topic_info_command(topic_name: String, server: &str) {
+    let data = Message {
+        kind: String::from("topic_info"),
+        topic: Some(topic_name),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("{}", error.message);
+        exit(1);
+    }
+
+    let details: TopicDetails = response.json().expect("Malformed topic info response");
+    let traffic = details.traffic;
+
+    println!("Topic:        {}", details.info.name);
+    println!("Message type: {}", details.info.message_type.unwrap_or("None".to_string()));
+    println!("Latched:      {}", if details.info.latched { "yes" } else { "no" });
+    println!("Publishers:   {}", details.info.publishers);
+    println!("Subscribers:  {}", details.info.subscribers);
+
+    for subscriber in details.connections {
+        println!(
+            "  {0: <6}{1: <24}{2: <24}{3: <10}{4: <7}{5: <16}{6} dropped",
+            subscriber.id,
+            subscriber.client,
+            subscriber.address,
+            subscriber.encoding.to_possible_value().unwrap().get_name(),
+            subscriber.compression.to_possible_value().unwrap().get_name(),
+            if subscriber.shared_memory { "shared memory" } else { "" },
+            subscriber.dropped,
+        );
+    }
+
+    println!("Messages:     {}", traffic.messages);
+    println!("Raw:          {} ({}/s)", format_bytes(traffic.raw_bytes as f64), format_bytes(rate(traffic.raw_bytes, traffic.seconds)));
+    println!("Sent:         {} ({}/s)", format_bytes(traffic.sent_bytes as f64), format_bytes(rate(traffic.sent_bytes, traffic.seconds)));
+
+    if traffic.raw_bytes > 0 {
+        println!("Compression:  {:.1}% of raw", traffic.sent_bytes as f64 * 100.0 / traffic.raw_bytes as f64);
+    }
+}
+
+/// Average amount of bytes per second
+fn rate(bytes: u64, seconds: f64) -> f64 {
+    if seconds > 0.0 { bytes as f64 / seconds } else { 0.0 }
+}
+
+/// Amount of bytes with a readable unit
+fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{:.0} {}", value, units[unit])
+    }
+    else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
@@ -0,0 +1,64 @@
+use std::net::Shutdown;
+use std::process::exit;
+use crate::message::message::Message;
+use crate::server::protocol::{connect, ErrorReply, Frame, FrameKind, send_request};
+use crate::server::serve::AtomicTopics;
+use crate::server::topic::{disconnect_subscribers, Disconnected, SharedSubscriber};
+use crate::server::transport::Connection;
+
+/// Server side topic kick, disconnects the subscribers matching the client
+pub fn handle_message_kind_topic_kick(mut stream: Connection, message: Message, topics: AtomicTopics) {
+    let topic_name = message.topic.unwrap();
+    let client = message.client.unwrap();
+
+    let kicked = topics.lock()
+        .iter_mut()
+        .find(|topic| topic.name == topic_name)
+        .map(|topic| {
+            let (kicked, kept): (Vec<SharedSubscriber>, Vec<SharedSubscriber>) = topic.subscribers
+                .drain(..)
+                .partition(|subscriber| subscriber.matches(&client));
+            topic.subscribers = kept;
+            kicked
+        });
+
+    match kicked {
+        Some(subscribers) if !subscribers.is_empty() => {
+            let subscribers = disconnect_subscribers(&topics, &topic_name, subscribers, "kicked by an administrator");
+            println!("Kicked {:?} from topic {}", subscribers, topic_name);
+            Frame::ack_with(&Disconnected { subscribers }).write_to(&mut stream).ok();
+        }
+        Some(_) => {
+            let error = format!("No subscriber of topic \"{}\" matches \"{}\"", topic_name, client);
+            Frame::error("unknown_client", error).write_to(&mut stream).ok();
+        }
+        None => {
+            let error = format!("Topic \"{}\" not found", topic_name);
+            Frame::error("unknown_topic", error).write_to(&mut stream).ok();
+        }
+    }
+
+    stream.shutdown(Shutdown::Both).ok();
+}
+
+/// Client side topic kick
+pub fn handle_topic_kick_command(topic_name: String, client: String, server: &str) {
+    let data = Message {
+        kind: String::from("topic_kick"),
+        topic: Some(topic_name.clone()),
+        client: Some(client),
+        ..Default::default()
+    };
+
+    let mut stream = connect(server);
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Could not kick client: {}", error.message);
+        exit(1);
+    }
+
+    let disconnected: Disconnected = response.json().expect("Malformed kick response");
+    println!("Kicked {} subscribers from topic \"{}\"", disconnected.subscribers.len(), topic_name);
+}
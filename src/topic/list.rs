@@ -1,26 +1,24 @@
-use std::io::Write;
-use std::net::{Shutdown, TcpStream};
-use crate::message::message::{get_topics};
+use std::net::Shutdown;
+use crate::message::message::{get_topics, TopicInfo};
+use crate::server::protocol::Frame;
 use crate::server::serve::{AtomicTopics};
+use crate::server::transport::Connection;
 
 
 /// Server side topic list
-pub fn handle_message_kind_list(mut stream: TcpStream, topics: AtomicTopics) {
-    let mut response = String::from("");
+pub fn handle_message_kind_list(mut stream: Connection, topics: AtomicTopics) {
+    let topics_info: Vec<TopicInfo> = topics.lock()
+        .iter()
+        .map(|topic| topic.info())
+        .collect();
 
-    for topic in topics.topics.lock().unwrap().iter() {
-        response += "- ";
-        response += &topic.name;
-        response.push('\n');
-    }
-
-    stream.write_all(response.as_bytes()).ok();
+    Frame::ack_with(&topics_info).write_to(&mut stream).ok();
     stream.shutdown(Shutdown::Both).ok();
 }
 
 /// Client side topic list
-pub fn handle_topic_list_command(with_message_types: bool) {
-    let topics = get_topics();
+pub fn handle_topic_list_command(with_message_types: bool, with_latched: bool, with_counts: bool, server: &str) {
+    let topics = get_topics(server);
 
     let mut separator = "--------------------".to_string();
 
@@ -31,21 +29,39 @@ pub fn handle_topic_list_command(with_message_types: bool) {
         separator += "--------------------";
     }
 
+    if with_latched {
+        print!("{0: <10}", "Latched");
+        separator += "----------";
+    }
+
+    if with_counts {
+        print!("{0: <13}{1: <12}", "Subscribers", "Publishers");
+        separator += "-------------------------";
+    }
+
     println!();
     println!("{separator}");
 
-    for (topic, message_type) in topics {
-        print!("{0: <20}", topic);
+    for topic in topics {
+        print!("{0: <20}", topic.name);
 
         if with_message_types {
-            if message_type.is_some() {
-                print!("{0: <20}", message_type.unwrap());
+            if let Some(message_type) = topic.message_type {
+                print!("{0: <20}", message_type);
             }
             else {
                 print!("{0: <20}", "None")
             }
         }
 
+        if with_latched {
+            print!("{0: <10}", if topic.latched { "yes" } else { "no" });
+        }
+
+        if with_counts {
+            print!("{0: <13}{1: <12}", topic.subscribers, topic.publishers);
+        }
+
         println!();
     }
 }
\ No newline at end of file
@@ -1,3 +1,7 @@
 pub mod tpub;
 pub mod tsub;
-pub mod list;
\ No newline at end of file
+pub mod list;
+pub mod delete;
+pub mod clear;
+pub mod kick;
+pub mod info;
\ No newline at end of file
@@ -1,45 +1,205 @@
-use std::io::Write;
-use std::net::{Shutdown, TcpStream};
+use std::io::ErrorKind;
+use std::net::Shutdown;
 use std::process::exit;
-use crate::message::message::{get_message_type, get_schema, Message, topic_exists};
-use crate::server::serve::{acknowledgement_http_request, AtomicTopics, handle_generic_topics, message_to_http_request};
+use crate::message::message::{get_message_type, get_schema, Message};
+use crate::server::handshake::Session;
+use crate::server::info::{publish_event, SystemEvent, timestamp_millis};
+use crate::server::protocol::{ErrorReply, connect, Frame, FrameKind, ProtocolError, send_encoded_request, TypeMismatch};
+use crate::server::compression::Compression;
+use crate::server::encoding::Encoding;
+use crate::server::serve::AtomicTopics;
+use crate::server::shared_memory::{COMMIT_TIMEOUT, Segment, SHARED_MEMORY_THRESHOLD, SharedMemoryDescriptor, SLOT_CAPACITY};
+use crate::server::topic::{Outgoing, validate_message};
+use crate::server::transport::Connection;
 
-/// Server side topic pub
-pub fn handle_message_kind_pub(mut stream: TcpStream, message: Message, topics: AtomicTopics) {
-    handle_generic_topics(message.clone().topic.unwrap());
+/// Server side topic pub, returns whether the message was published
+pub fn handle_message_kind_pub(mut stream: Connection, message: Message, session: &Session, topics: AtomicTopics) -> bool {
+    let topic_name = message.topic.clone().unwrap();
+    let received = timestamp_millis();
 
-    let mut bytes_to_send = Vec::new();
+    // Validation happens out of the topics lock, only the schema is kept
+    let topic = topics.lock()
+        .iter()
+        .find(|topic| topic.name == topic_name)
+        .map(|topic| (topic.message_type.clone(), topic.schema.clone(), topic.subscribers.len(), topic.is_retypable(), topic.access.clone()));
 
-    if let Some(content) = &message.message.as_ref() {
-        bytes_to_send = [bytes_to_send, serde_json::to_vec(content).unwrap()].concat();
+    let Some((message_type, schema, subscribers, retypable, access)) = topic else {
+        let error = format!("Topic \"{}\" not found", topic_name);
+        Frame::error("unknown_topic", error).write_to(&mut stream).ok();
+        return false;
+    };
+
+    let publisher = stream.peer_ip();
+
+    if !access.can_publish(publisher) {
+        let error = format!("Host is not allowed to publish on topic \"{}\"", topic_name);
+        Frame::error("access_denied", error).write_to(&mut stream).ok();
+        return false;
     }
 
-    for topic in topics.topics.lock().as_deref_mut().unwrap() {
-        if topic.name == message.topic.as_ref().unwrap().clone() {
-            topic.write_to_subscribers(&bytes_to_send);
-        }
+    if message_type != message.message_type {
+        let mismatch = TypeMismatch {
+            topic: topic_name.clone(),
+            expected: message_type,
+            requested: message.message_type,
+            subscribers,
+            retypable,
+        };
+        mismatch.to_frame().write_to(&mut stream).ok();
+
+        publish_event(&topics, SystemEvent::ValidationFailed {
+            topic: topic_name,
+            client: session.client(),
+            errors: vec![mismatch.to_string()],
+        });
+        return false;
     }
 
+    // Local publishers of large messages write them in the shared memory of the topic, then commit them
+    let shared = match message.shared_memory_length {
+        Some(length) => match receive_shared_message(&mut stream, &topics, &topic_name, length) {
+            Ok(shared) => Some(shared),
+            Err(error) => {
+                error.write_to(&mut stream).ok();
+                return false;
+            }
+        },
+        None => None,
+    };
+
     stream.shutdown(Shutdown::Read).ok();
 
-    let response = acknowledgement_http_request();
-    stream.write_all(response.as_bytes()).unwrap();
+    let content = match &shared {
+        Some((_, encoding, payload)) => match encoding.decode(payload) {
+            Ok(content) => Some(content),
+            Err(error) => {
+                let error = format!("Message written in shared memory is not valid {:?}: {}", encoding, error);
+                Frame::error("invalid_message", error).write_to(&mut stream).ok();
+                return false;
+            }
+        },
+        None => message.message,
+    };
+
+    if let Some(schema) = schema {
+        if let Err(errors) = validate_message(&schema, content.as_ref()) {
+            let error = format!("Wrong message format, should be \"{}\" for topic \"{}\"", message_type.unwrap(), topic_name);
+            println!("Rejected message to topic {}: {}", topic_name, errors.join(", "));
+            Frame::error_with_details("invalid_message", error, errors.clone()).write_to(&mut stream).ok();
+
+            publish_event(&topics, SystemEvent::ValidationFailed {
+                topic: topic_name,
+                client: session.client(),
+                errors,
+            });
+            return false;
+        }
+    }
+
+    // Messages are kept and validated as JSON, subscribers using another encoding get them transcoded
+    let (bytes_to_send, shared) = match shared {
+        Some((descriptor, Encoding::Json, payload)) => (payload, Some((descriptor, Encoding::Json))),
+        shared => (
+            content.map(|content| serde_json::to_vec(&content).unwrap()).unwrap_or_default(),
+            shared.map(|(descriptor, encoding, _)| (descriptor, encoding)),
+        ),
+    };
+
+    for topic in topics.lock().iter_mut().filter(|topic| topic.name == topic_name) {
+        topic.publishers.extend(publisher);
+    }
+
+    let outgoing = match shared {
+        Some((descriptor, encoding)) => Outgoing::shared(bytes_to_send, descriptor, encoding),
+        None => Outgoing::new(bytes_to_send),
+    };
+    let outgoing = outgoing.published_by(session.client(), message.timestamp, received);
+
+    topics.write_to_subscribers(&topic_name, outgoing);
+
+    Frame::ack().write_to(&mut stream).ok();
+
+    println!("Sent message to topic {}", topic_name);
+
+    true
+}
+
+/// Hands a slot of the topic segment out to a local publisher and waits for it to commit the message written in it
+///
+/// Returns the descriptor of the message, its encoding and a copy of it, used for the validation, the retained message
+/// and the subscribers reading the socket
+fn receive_shared_message(stream: &mut Connection, topics: &AtomicTopics, topic_name: &str, length: u64) -> Result<(SharedMemoryDescriptor, Encoding, Vec<u8>), Frame> {
+    if !stream.is_local() {
+        return Err(Frame::error("shared_memory_unavailable", "Shared memory is only available to clients on the server host".to_string()));
+    }
+
+    // The segment is only created once a subscriber can read it, the others get the message over the socket anyway
+    let segment = topics.lock()
+        .iter_mut()
+        .find(|topic| topic.name == topic_name)
+        .map(|topic| match topic.subscribers.iter().any(|subscriber| subscriber.shared_memory) {
+            true => topic.shared_segment().map(Some),
+            false => Ok(None),
+        });
+
+    let segment = match segment {
+        Some(Ok(Some(segment))) => segment,
+        Some(Ok(None)) => return Err(Frame::error("shared_memory_unavailable", "No subscriber of the topic reads shared memory".to_string())),
+        Some(Err(error)) => return Err(Frame::error("shared_memory_unavailable", format!("Could not create the shared memory of the topic: {}", error))),
+        None => return Err(Frame::error("unknown_topic", format!("Topic \"{}\" not found", topic_name))),
+    };
+
+    let Some(descriptor) = segment.claim(length) else {
+        let error = format!("No free slot of {} bytes in the shared memory of the topic", SLOT_CAPACITY);
+        return Err(Frame::error("shared_memory_unavailable", error));
+    };
+
+    let received = receive_commit(stream, &segment, &descriptor);
+
+    // A publisher that never commits must not keep its slot
+    segment.release(&descriptor, received.is_ok());
+
+    received.map(|(encoding, payload)| (descriptor, encoding, payload))
+}
+
+/// Waits for the publisher to commit the message written in the slot of the descriptor, then copies it
+fn receive_commit(stream: &mut Connection, segment: &Segment, descriptor: &SharedMemoryDescriptor) -> Result<(Encoding, Vec<u8>), Frame> {
+    Frame::ack_with(descriptor).write_to(stream).map_err(|error| Frame::error("io_error", error.to_string()))?;
+
+    stream.set_read_timeout(Some(COMMIT_TIMEOUT)).ok();
+
+    let commit = match Frame::read_from(stream) {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return Err(Frame::error("io_error", "Connection closed before the message was committed".to_string())),
+        Err(ProtocolError::Io(error)) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            return Err(Frame::error("commit_timeout", format!("The message was not committed within {} seconds", COMMIT_TIMEOUT.as_secs())));
+        }
+        Err(error) => return Err(error.to_frame()),
+    };
+
+    if commit.shared_memory_descriptor().as_ref() != Some(descriptor) {
+        return Err(Frame::error("unexpected_frame", "Expected the descriptor of the message written in shared memory".to_string()));
+    }
 
-    println!("Sent message from {} to topic {}", stream.peer_addr().unwrap(), message.topic.unwrap());
+    let encoding = commit.encoding().ok_or(ProtocolError::UnknownEncoding(commit.flags).to_frame())?;
+
+    match segment.read(descriptor) {
+        Some(payload) => Ok((encoding, payload)),
+        None => Err(Frame::error("shared_memory_overrun", "The slot was overwritten before the message was committed".to_string())),
+    }
 }
 
 /// Client side topic pub
-pub fn handle_topic_pub_command(topic_name: String, message: Option<String>) {
-    if !topic_exists(topic_name.clone()) {
+pub fn handle_topic_pub_command(topic_name: String, message: Option<String>, encoding: Encoding, compression: Compression, server: &str) {
+    // The topics are only listed once, each listing is a request to the server
+    let Some(message_type) = get_message_type(topic_name.clone(), server) else {
         println!("Topic \"{}\" not found", topic_name);
         exit(1);
-    }
+    };
 
     let data: Message;
 
 
-    let message_type = get_message_type(topic_name.clone()).unwrap();
-
     if message_type.is_some() {
         if message.is_some() {
             let schema = get_schema(message_type.clone().unwrap());
@@ -59,7 +219,8 @@ pub fn handle_topic_pub_command(topic_name: String, message: Option<String>) {
                 kind: String::from("pub"),
                 topic: Some(topic_name),
                 message_type,
-                message: Some(content)
+                message: Some(content),
+                ..Default::default()
             };
         }
         else {
@@ -79,12 +240,81 @@ pub fn handle_topic_pub_command(topic_name: String, message: Option<String>) {
             kind: String::from("pub"),
             topic: Some(String::from(topic_name)),
             message_type: None,
-            message: None
+            message: None,
+            ..Default::default()
         };
     }
 
-    let mut stream = TcpStream::connect("127.0.0.1:1312").unwrap();
+    let data = Message {
+        timestamp: Some(timestamp_millis()),
+        ..data
+    };
+
+    let response = match publish_through_shared_memory(&data, encoding, server) {
+        Some(response) => response,
+        None => send_encoded_request(&mut connect(server), &data, encoding, compression).expect("Could not reach the server"),
+    };
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+        println!("Message rejected: {}", error.message);
+
+        for detail in error.details {
+            println!("  - {}", detail);
+        }
+
+        exit(1);
+    }
+}
+
+/// Publishes a large message through the shared memory of the topic when the server is on this host
+///
+/// Returns the server response, None if the message must be sent over the socket instead
+fn publish_through_shared_memory(data: &Message, encoding: Encoding, server: &str) -> Option<Frame> {
+    let payload = encoding.encode(data.message.as_ref()?);
+
+    if payload.len() < SHARED_MEMORY_THRESHOLD || payload.len() as u64 > SLOT_CAPACITY {
+        return None;
+    }
+
+    let mut stream = connect(server);
+
+    if !stream.is_local() {
+        return None;
+    }
+
+    let request = Message {
+        message: None,
+        shared_memory_length: Some(payload.len() as u64),
+        ..data.clone()
+    };
+
+    // Only the message is written in shared memory, the request is small enough as it is
+    let response = send_encoded_request(&mut stream, &request, encoding, Compression::None).expect("Could not reach the server");
+
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
+
+        // Segments cannot always be created, the socket still works
+        if error.code == "shared_memory_unavailable" {
+            return None;
+        }
+
+        return Some(response);
+    }
+
+    let descriptor: SharedMemoryDescriptor = response.json().expect("Malformed shared memory response");
+
+    let written = Segment::open(&descriptor.path).and_then(|segment| segment.write(&descriptor, &payload));
+
+    if let Err(error) = written {
+        println!("Could not write in shared memory \"{}\", sending the message over the socket: {}", descriptor.path.display(), error);
+        return None;
+    }
+
+    Frame::shared_memory(&descriptor).encoded(encoding).write_to(&mut stream).expect("Could not reach the server");
 
-    let request = message_to_http_request(&data);
-    stream.write_all(request.as_bytes()).ok();
+    Some(Frame::read_from(&mut stream)
+        .expect("Could not reach the server")
+        .expect("Connection closed by the server"))
 }
\ No newline at end of file
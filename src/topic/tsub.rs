@@ -1,80 +1,290 @@
+use std::collections::HashMap;
 use std::io::Write;
-use std::net::{Shutdown, TcpStream};
+use std::net::Shutdown;
+use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Arc;
 use jsonschema::JSONSchema;
-use generic_robot_framework::models::topic::Topic;
-use crate::message::message::{get_message_type, get_schema, is_message_type_registered, Message, topic_exists};
-use crate::server::serve::{acknowledgement_http_request, AtomicTopics, BAD_REQUEST_HTTP_STATUS, message_to_http_request, OK_HTTP_STATUS, single_request_to_string};
+use crate::message::message::{get_message_type, get_schema, is_message_type_registered, Message};
+use crate::server::info::{publish_event, SystemEvent};
+use crate::server::handshake::Session;
+use crate::server::topic::{Outgoing, Subscriber, Subscription, Topic};
+use crate::server::compression::Compression;
+use crate::server::encoding::Encoding;
+use crate::server::qos::{QoS, Reliability};
+use crate::server::protocol::{clone_connection, ErrorReply, connect, FLAG_HEADER, Frame, FrameKind, MessageEnvelope, MessageHeader, send_request, TypeMismatch};
+use crate::server::serve::AtomicTopics;
+use crate::server::shared_memory::{Segment, SharedMemoryDescriptor};
+use crate::server::transport::Connection;
+
+/// Exit code of `grf topic sub` when the topic uses another message type
+pub const TYPE_MISMATCH_EXIT_CODE: i32 = 3;
+
+/// Topic described by a sub request creating it
+fn new_topic(topic_name: &str, message: &Message) -> Result<Topic, String> {
+    let topic = match message.message_type.clone() {
+        Some(message_type) if message.validation.unwrap_or(true) => {
+            Topic::with_validation(topic_name, message_type)
+        }
+        message_type => Ok(Topic::new(topic_name, message_type)),
+    };
+
+    topic.map(|topic| topic.latched(message.latched.unwrap_or(false)))
+}
+
+/// Copies a message published through shared memory, each segment is only mapped once
+fn read_shared_message(segments: &mut HashMap<PathBuf, Segment>, descriptor: &SharedMemoryDescriptor) -> Result<Vec<u8>, String> {
+    if !segments.contains_key(&descriptor.path) {
+        let segment = Segment::open(&descriptor.path)
+            .map_err(|error| format!("could not open shared memory \"{}\": {}", descriptor.path.display(), error))?;
+
+        segments.insert(descriptor.path.clone(), segment);
+    }
+
+    segments[&descriptor.path]
+        .read(descriptor)
+        .ok_or_else(|| "it was overwritten in shared memory before being read".to_string())
+}
+
+/// Converts a message to JSON, taking it out of its envelope if it came along with its header
+fn message_to_json(encoding: Encoding, payload: &[u8], enveloped: bool) -> Result<(Option<MessageHeader>, Vec<u8>), String> {
+    if !enveloped {
+        return encoding.to_json(payload).map(|payload| (None, payload));
+    }
+
+    let envelope: MessageEnvelope = encoding.decode(payload)?;
+    let payload = envelope.message.map(|message| serde_json::to_vec(&message).unwrap()).unwrap_or_default();
+
+    Ok((Some(envelope.header), payload))
+}
+
+/// Warns about the messages missed since the previous one, or sent out of order
+fn warn_about_sequence(sequence: u64, last_sequence: Option<u64>) {
+    match last_sequence {
+        Some(last) if sequence > last + 1 => println!("Missed {} messages", sequence - last - 1),
+        Some(last) if sequence <= last => println!("Out of order message, sequence {} after {}", sequence, last),
+        _ => {}
+    }
+}
+
+/// Prints the header of a message, timestamps are in milliseconds since the Unix epoch
+fn print_header(header: &MessageHeader) {
+    println!("Sequence:  {}", header.sequence);
+    println!("Publisher: {}", header.publisher);
+
+    if let Some(published) = header.published {
+        println!("Published: {}", published);
+    }
+
+    match header.published {
+        Some(published) => println!("Received:  {} ({} ms after publishing)", header.received, header.received.saturating_sub(published)),
+        None => println!("Received:  {}", header.received),
+    }
+}
 
 /// Server side topic sub
-pub fn handle_message_kind_sub(mut stream: TcpStream, message: Message, topics: AtomicTopics) {
+pub fn handle_message_kind_sub(mut stream: Connection, message: Message, session: &Session, topics: AtomicTopics) {
 
-    let mut subscribed = false;
-    let mut topic_exists = false;
+    let mut topic_event = None;
     let topic_name = message.topic.as_ref().unwrap().clone();
 
-    for topic in topics.topics.lock().unwrap().iter_mut() {
-        if topic.name == topic_name {
-            topic_exists = true;
+    let topic_qos = topics.lock()
+        .iter()
+        .find(|topic| topic.name == topic_name)
+        .and_then(|topic| topic.qos);
 
-            if message.message_type == topic.message_type {
-                let new_sub = stream.try_clone().unwrap();
+    let qos = QoS::negotiate(topic_qos.or(message.qos));
 
-                topic.subscribers.push(new_sub);
-                subscribed = true;
+    // A ring slot can be overwritten before a lagging subscriber reads it, reliable subscribers get the messages themselves
+    let shared_memory = message.shared_memory.unwrap_or(false)
+        && session.hello.capabilities.shared_memory
+        && stream.is_local()
+        && qos.reliability == Reliability::BestEffort;
+
+    let Some(subscriber_stream) = clone_connection(&mut stream) else {
+        return;
+    };
+
+    // Messages published before the acknowledgement wait in the queue, the writer is only started after it
+    let new_sub = Subscriber::new(
+        subscriber_stream,
+        session,
+        qos,
+        shared_memory,
+        message.encoding.unwrap_or_default(),
+        message.compression.unwrap_or_default(),
+        message.header.unwrap_or(false),
+    );
+    let host = stream.peer_ip();
+
+    let result = {
+        let mut topics_list = topics.lock();
+
+        match topics_list.iter_mut().find(|topic| topic.name == topic_name) {
+            Some(topic) if !topic.access.can_subscribe(host) => {
+                let error = format!("Host is not allowed to subscribe to topic \"{}\"", topic_name);
+                Err((Frame::error("access_denied", error), None))
+            }
+            Some(topic) if message.message_type == topic.message_type => {
+                topic.subscribers.push(Arc::clone(&new_sub));
+                Ok(topic.retained.clone().map(|retained| (retained, topic.retained_header.clone())))
+            }
+            // Nobody relies on the type of a topic without subscribers, its retained message is dropped with it
+            Some(topic) if message.retype.unwrap_or(false) && topic.is_retypable() => {
+                match new_topic(&topic_name, &message) {
+                    Ok(mut retyped) => {
+                        retyped.subscribers.push(Arc::clone(&new_sub));
+                        *topic = retyped;
+
+                        topic_event = Some(SystemEvent::TopicRetyped {
+                            topic: topic_name.clone(),
+                            message_type: message.message_type.clone(),
+                        });
+                        Ok(None)
+                    }
+                    Err(error) => Err((Frame::error("unknown_message_type", error), None)),
+                }
+            }
+            Some(topic) => {
+                let mismatch = TypeMismatch {
+                    topic: topic_name.clone(),
+                    expected: topic.message_type.clone(),
+                    requested: message.message_type.clone(),
+                    subscribers: topic.subscribers.len(),
+                    retypable: topic.is_retypable(),
+                };
+
+                Err((mismatch.to_frame(), Some(mismatch.to_string())))
+            }
+            // If topic doesn't exist, create it
+            None => {
+                match new_topic(&topic_name, &message) {
+                    Ok(mut topic) => {
+                        topic.subscribers.push(Arc::clone(&new_sub));
+                        topics_list.push(topic);
+
+                        topic_event = Some(SystemEvent::TopicCreated {
+                            topic: topic_name.clone(),
+                            message_type: message.message_type.clone(),
+                        });
+                        Ok(None)
+                    }
+                    Err(error) => Err((Frame::error("unknown_message_type", error), None)),
+                }
             }
         }
+    };
+
+    let client = session.client();
+
+    if let Some(event) = topic_event {
+        topics.topics_to_file();
+        publish_event(&topics, event);
     }
 
-    // If topic doesn't exist, create it
-    if !topic_exists {
-        let new_sub = stream.try_clone().unwrap();
+    match result {
+        Ok(retained) => {
+            let subscription = Subscription {
+                subscriber: new_sub.id,
+                qos: new_sub.qos,
+                shared_memory: new_sub.shared_memory,
+                encoding: new_sub.encoding,
+                compression: new_sub.compression,
+                header: new_sub.header,
+            };
 
-        topics.topics.lock().unwrap().push(Topic {
-            name: topic_name,
-            message_type: message.message_type,
-            subscribers: vec![new_sub],
-        });
+            Frame::ack_with(&subscription).write_to(&mut stream).ok();
+            println!("Subscribed {} to topic {} with {:?}", new_sub.id, topic_name, new_sub.qos);
 
-        topics.topics_to_file();
+            // Late joiners of latched topics get the last message right away
+            if let Some((retained, header)) = retained {
+                let mut retained = Outgoing::new(retained);
+                // Messages kept by servers predating the headers are given sequence 0
+                let header = header.unwrap_or_else(|| retained.stamp(0));
+                if let Some((frame, _)) = retained.with_header(header).frame_for(&new_sub) {
+                    stream.write_all(&frame).ok();
+                }
+            }
 
-        subscribed = true;
-    }
+            publish_event(&topics, SystemEvent::SubscriptionAdded {
+                topic: topic_name.clone(),
+                subscriber: new_sub.id,
+                client,
+            });
 
-    if  subscribed {
-        let response = acknowledgement_http_request();
-        stream.write_all(response.as_bytes()).unwrap();
-        println!("Subscribed {} to topic {}", stream.peer_addr().unwrap(), message.topic.unwrap());
+            new_sub.start_writer(topic_name, topics);
+        }
+        Err((error, validation_error)) => {
+            error.write_to(&mut stream).ok();
+
+            if let Some(validation_error) = validation_error {
+                publish_event(&topics, SystemEvent::ValidationFailed {
+                    topic: topic_name,
+                    client,
+                    errors: vec![validation_error],
+                });
+            }
+        }
     }
-    else {
-        let response = BAD_REQUEST_HTTP_STATUS.to_string();
-        stream.write_all(response.as_bytes()).unwrap();
+}
+
+/// Server side topic unsub
+pub fn handle_message_kind_unsub(mut stream: Connection, message: Message, topics: AtomicTopics) {
+    let topic_name = message.topic.unwrap();
+    let id = message.subscriber.unwrap();
+
+    let removed = topics.lock()
+        .iter_mut()
+        .find(|topic| topic.name == topic_name)
+        .and_then(|topic| topic.remove_subscriber(id));
+
+    match removed {
+        Some(subscriber) => {
+            subscriber.close();
+            Frame::ack().write_to(&mut stream).ok();
+            println!("Unsubscribed {} from topic {}", id, topic_name);
+
+            publish_event(&topics, SystemEvent::SubscriptionRemoved {
+                topic: topic_name,
+                subscriber: id,
+            });
+        }
+        None => {
+            let error = format!("No subscriber {} on topic \"{}\"", id, topic_name);
+            Frame::error("unknown_subscriber", error).write_to(&mut stream).ok();
+        }
     }
 }
 
 
 /// Client side topic sub
-pub fn handle_topic_sub_command(topic_name: String, create_topic_message_type: Option<Option<String>>) {
+#[allow(clippy::too_many_arguments)]
+pub fn handle_topic_sub_command(topic_name: String, create_topic_message_type: Option<Option<String>>, validation: bool, latched: bool, retype: bool, qos: QoS, encoding: Encoding, compression: Compression, header: bool, server: &str) {
     println!("Subscribing to topic \"{topic_name}\"");
 
-    let mut stream = TcpStream::connect("127.0.0.1:1312").unwrap();
+    let mut stream = connect(server);
 
     let data;
     let validation_schema: Option<JSONSchema>;
 
     if create_topic_message_type.is_none() {
-        if !topic_exists(topic_name.clone()) {
+        // The topics are only listed once, each listing is a request to the server
+        let Some(message_type) = get_message_type(topic_name.clone(), server) else {
             println!("Topic \"{}\" not found", topic_name);
             exit(1);
-        }
-
-        let message_type = get_message_type(topic_name.clone()).unwrap();
+        };
 
         data = Message {
             kind: String::from("sub"),
             topic: Some(topic_name),
             message_type: message_type.clone(),
             message: None,
+            qos: Some(qos),
+            shared_memory: Some(true),
+            encoding: Some(encoding),
+            compression: Some(compression),
+            header: Some(header),
+            ..Default::default()
         };
 
         if message_type.clone().is_some() {
@@ -95,6 +305,15 @@ pub fn handle_topic_sub_command(topic_name: String, create_topic_message_type: O
                 topic: Some(topic_name),
                 message_type: create_topic_message_type.clone().unwrap(),
                 message: None,
+                validation: Some(validation),
+                latched: Some(latched),
+                retype: Some(retype),
+                qos: Some(qos),
+                shared_memory: Some(true),
+                encoding: Some(encoding),
+                compression: Some(compression),
+                header: Some(header),
+                ..Default::default()
             };
 
             validation_schema = Some(get_schema(create_topic_message_type.unwrap().unwrap()));
@@ -105,6 +324,14 @@ pub fn handle_topic_sub_command(topic_name: String, create_topic_message_type: O
                 topic: Some(topic_name),
                 message_type: None,
                 message: None,
+                latched: Some(latched),
+                retype: Some(retype),
+                qos: Some(qos),
+                shared_memory: Some(true),
+                encoding: Some(encoding),
+                compression: Some(compression),
+                header: Some(header),
+                ..Default::default()
             };
 
             validation_schema = None;
@@ -113,49 +340,140 @@ pub fn handle_topic_sub_command(topic_name: String, create_topic_message_type: O
         println!("Created topic");
     }
 
-    let request = message_to_http_request(&data);
-    stream.write_all(request.as_bytes()).ok();
+    let response = send_request(&mut stream, &data).expect("Could not reach the server");
     stream.shutdown(Shutdown::Write).ok();
 
-    let response = single_request_to_string(&mut stream);
+    if response.kind == FrameKind::Error {
+        let error: ErrorReply = response.json().expect("Malformed error response");
 
-    if response != OK_HTTP_STATUS {
-        panic!("Bad response: {}", response)
+        if let Some(mismatch) = error.type_mismatch {
+            println!("Cannot subscribe to topic \"{}\", its message type does not match", mismatch.topic);
+            println!("  Expected:  {}", mismatch.expected.unwrap_or("None".to_string()));
+            println!("  Requested: {}", mismatch.requested.unwrap_or("None".to_string()));
+
+            if mismatch.retypable {
+                println!("The topic has no subscribers, use --force-retype to change its message type");
+            }
+            else if mismatch.subscribers > 0 {
+                println!("The message type cannot be changed while the topic has {} subscribers", mismatch.subscribers);
+            }
+            else {
+                println!("The message type of a system or declared topic cannot be changed");
+            }
+
+            exit(TYPE_MISMATCH_EXIT_CODE);
+        }
+
+        println!("Subscription rejected: {}", error.message);
+
+        for detail in error.details {
+            println!("  - {}", detail);
+        }
+
+        exit(1);
     }
-    else {
-        println!("Subscribed");
+
+    println!("Subscribed");
+
+    let subscription: Subscription = response.json().expect("Malformed subscription response");
+
+    if subscription.qos != qos {
+        println!("Server granted {:?}", subscription.qos);
     }
+    let unsub = Message {
+        kind: String::from("unsub"),
+        topic: data.topic.clone(),
+        subscriber: Some(subscription.subscriber),
+        ..Default::default()
+    };
+    let server = server.to_string();
 
-    loop {
-        let response = single_request_to_string(&mut stream);
+    // Tell the server we are leaving so that the topic does not keep a dead subscriber
+    ctrlc::set_handler(move || {
+        let mut unsub_stream = connect(&server);
+        send_request(&mut unsub_stream, &unsub).ok();
+        exit(0);
+    }).expect("Could not set the interruption handler");
+
+    let mut segments = HashMap::new();
+    let mut last_sequence = None;
+
+    while let Some(frame) = Frame::read_from(&mut stream).expect("Connection to the server lost") {
+        if frame.kind == FrameKind::Close {
+            let reason: String = frame.json().unwrap_or_default();
+            println!("Server closed the subscription: {}", reason);
+            exit(0);
+        }
 
-        if response.len() > 0 {
+        if frame.kind != FrameKind::Data {
+            continue;
+        }
 
-            dbg!(&response);
+        let frame = match frame.decompressed() {
+            Ok(frame) => frame,
+            Err(error) => {
+                println!("Got undecodable message: {}", error);
+                continue;
+            }
+        };
 
-            if validation_schema.is_some() {
-                let data_to_validate = serde_json::from_str(response.as_str()).unwrap();
-                let result = validation_schema.as_ref().unwrap().validate(&data_to_validate);
+        let message_encoding = frame.encoding();
+        let enveloped = frame.flags & FLAG_HEADER != 0;
 
-                if result.is_err() {
-                    println!("Got badly formatted message: {}", response)
+        let (shared_header, payload) = match frame.shared_memory_descriptor() {
+            Some(descriptor) => match read_shared_message(&mut segments, &descriptor) {
+                Ok(payload) => (descriptor.header, payload),
+                Err(error) => {
+                    println!("Dropped a message: {}", error);
+                    continue;
                 }
-                else {
-                    println!("---");
-                    println!("{response}");
+            },
+            None => (None, frame.payload),
+        };
+
+        // Messages are shown as JSON whatever the encoding they were sent in
+        let (message_header, payload) = match message_encoding.ok_or("unknown encoding".to_string()).and_then(|encoding| message_to_json(encoding, &payload, enveloped)) {
+            Ok(message) => message,
+            Err(error) => {
+                println!("Got undecodable message: {}", error);
+                continue;
+            }
+        };
+
+        let message_header = shared_header.or(message_header);
+
+        if let Some(message_header) = &message_header {
+            warn_about_sequence(message_header.sequence, last_sequence);
+            last_sequence = Some(message_header.sequence);
+        }
+
+        let response = String::from_utf8_lossy(&payload);
+
+        if let Some(schema) = validation_schema.as_ref() {
+            let result = serde_json::from_slice(&payload)
+                .map(|data_to_validate| schema.is_valid(&data_to_validate));
+
+            if let Ok(true) = result {
+                println!("---");
+                if let Some(message_header) = &message_header {
+                    print_header(message_header);
                 }
+                println!("{response}");
             }
             else {
-                if response.len() > 1 {
-                    println!("Got badly formatted message: {}", response)
-                }
-                else {
-                    println!("---");
-                }
+                println!("Got badly formatted message: {}", response)
             }
         }
         else {
-            break;
+            // Untyped topics only carry content when published by the server, such as the parameter changes
+            println!("---");
+            if let Some(message_header) = &message_header {
+                print_header(message_header);
+            }
+
+            if !payload.is_empty() {
+                println!("{response}");
+            }
         }
     }
 }
\ No newline at end of file